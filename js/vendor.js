@@ -0,0 +1,3 @@
+// Everything src/auth.js imports from agent-js, bundled into public/vendor/dfinity.js
+export { AuthClient } from "@dfinity/auth-client";
+export { DelegationChain, Ed25519KeyIdentity } from "@dfinity/identity";
@@ -1,12 +1,110 @@
-use crate::ic_agent::{create_client_from_config, ICClient, ICConfig};
-use crate::server_functions::{CallerAction, ExecuteCallerAction};
+use crate::counter_stream::{COUNTER_STREAM_PATH, RECONNECT_DELAY};
+use crate::ic_agent::{CounterValue, ExportFormat, ThresholdWatch};
+use crate::server_functions::{
+    execute_counter_action, get_deployment_info, CallerAction, CallerErrorKind, CallerResult,
+    DeploymentInfo, ExecuteCallerAction, ExportCounterState,
+};
 use leptos::prelude::*;
 use leptos::task::spawn_local;
+use leptos::wasm_bindgen::{closure::Closure, JsCast};
 use leptos_meta::{provide_meta_context, MetaTags, Stylesheet, Title};
 use leptos_router::{
     components::{Route, Router, Routes},
     StaticSegment,
 };
+use std::collections::HashMap;
+use std::mem::{discriminant, Discriminant};
+use std::time::Duration;
+use web_sys::{EventSource, HtmlElement, KeyboardEvent, MessageEvent};
+use web_time::{Instant, SystemTime, UNIX_EPOCH};
+#[cfg(feature = "client-calls")]
+use {
+    crate::auth,
+    crate::ic_agent::{create_client_from_config, ICClient, ICConfig, DEPLOYMENT_ENVS},
+    futures::future::{abortable, AbortHandle},
+    ic_agent::{export::Principal, identity::AnonymousIdentity},
+    std::sync::Arc,
+};
+
+/// Environment the browser-side client targets until the user picks another
+#[cfg(feature = "client-calls")]
+const DEFAULT_DEPLOYMENT_ENV: &str = "prod";
+
+/// How long a browser-side call may hang, e.g. on a stalled boundary node, before
+/// the buttons are given back. The client times out with `setTimeout` in the
+/// browser, since there is no tokio timer in wasm.
+#[cfg(feature = "client-calls")]
+const CLIENT_CALL_TIMEOUT: Duration = Duration::from_secs(20);
+
+/// How often the connection indicator pings the replica while it answers
+#[cfg(feature = "client-calls")]
+const PING_INTERVAL: Duration = Duration::from_secs(15);
+
+/// Longest wait between reconnect attempts while the replica stays down
+#[cfg(feature = "client-calls")]
+const MAX_RECONNECT_BACKOFF: Duration = Duration::from_secs(4 * 60);
+
+/// `localStorage` key remembering the browser-side client's environment
+#[cfg(feature = "client-calls")]
+const DEPLOYMENT_ENV_KEY: &str = "onboarding-counter.deployment-env";
+
+#[cfg(feature = "client-calls")]
+fn load_deployment_env() -> Option<String> {
+    window()
+        .local_storage()
+        .ok()
+        .flatten()?
+        .get_item(DEPLOYMENT_ENV_KEY)
+        .ok()
+        .flatten()
+        .filter(|env| DEPLOYMENT_ENVS.contains(&env.as_str()))
+}
+
+#[cfg(feature = "client-calls")]
+fn save_deployment_env(env: &str) {
+    if let Ok(Some(storage)) = window().local_storage() {
+        _ = storage.set_item(DEPLOYMENT_ENV_KEY, env);
+    }
+}
+
+/// `localStorage` key holding the default counter's last value fetched in this
+/// browser, shown on the next visit until a fresh read comes back
+const LAST_VALUE_KEY: &str = "onboarding-counter.last-value";
+
+fn load_cached_value() -> Option<CounterValue> {
+    window()
+        .local_storage()
+        .ok()
+        .flatten()?
+        .get_item(LAST_VALUE_KEY)
+        .ok()
+        .flatten()?
+        .parse()
+        .ok()
+        .map(CounterValue)
+}
+
+/// Remember `value` for [`load_cached_value`], if it is the default counter's
+fn save_cached_value(counter: Option<&str>, value: CounterValue) {
+    if counter.is_some() {
+        return;
+    }
+    if let Ok(Some(storage)) = window().local_storage() {
+        _ = storage.set_item(LAST_VALUE_KEY, &value.to_string());
+    }
+}
+
+/// [`save_cached_value`] for a value read on `target`. Only the browser's prod
+/// counter is the default one the server buttons read.
+fn save_target_value(target: &HistoryTarget, value: CounterValue) {
+    match target {
+        HistoryTarget::Server { counter } => save_cached_value(counter.as_deref(), value),
+        #[cfg(feature = "client-calls")]
+        HistoryTarget::Client { env: "prod" } => save_cached_value(None, value),
+        #[cfg(feature = "client-calls")]
+        HistoryTarget::Client { .. } => {}
+    }
+}
 
 pub fn shell(options: LeptosOptions) -> impl IntoView {
     view! {
@@ -26,104 +124,604 @@ pub fn shell(options: LeptosOptions) -> impl IntoView {
     }
 }
 
+/// Which counter an action went to and how: the two button groups can point at
+/// different environments and counters, so undo has to go back the same way
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum HistoryTarget {
+    /// Through the server, on one of its named counters or the default one
+    Server { counter: Option<String> },
+    /// Straight from the browser, to this deployment environment's counter
+    #[cfg(feature = "client-calls")]
+    Client { env: &'static str },
+}
+
+impl std::fmt::Display for HistoryTarget {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HistoryTarget::Server { counter: None } => write!(f, "server"),
+            HistoryTarget::Server {
+                counter: Some(counter),
+            } => write!(f, "server, {} counter", counter),
+            #[cfg(feature = "client-calls")]
+            HistoryTarget::Client { env } => write!(f, "browser, {}", env),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct HistoryEntry {
+    pub action: CallerAction,
+    pub value: CounterValue,
+    pub at: SystemTime,
+    pub target: HistoryTarget,
+}
+
+/// Successful counter actions in the order they completed, with the value each one reported
+#[derive(Clone, Debug, Default)]
+pub struct CounterHistory(Vec<HistoryEntry>);
+
+/// How to revert one history entry
+#[derive(Clone, Debug)]
+struct Undo {
+    index: usize,
+    undone: CallerAction,
+    /// The value the counter should still hold if nobody else touched it
+    expected: CounterValue,
+    inverse: CallerAction,
+    target: HistoryTarget,
+}
+
+impl CounterHistory {
+    pub fn record(&mut self, target: HistoryTarget, action: CallerAction, value: CounterValue) {
+        self.0.push(HistoryEntry {
+            action,
+            value,
+            at: SystemTime::now(),
+            target,
+        });
+    }
+
+    pub fn entries(&self) -> &[HistoryEntry] {
+        &self.0
+    }
+
+    /// Whether the value most recently seen on `target` is zero, so decrementing
+    /// there would underflow
+    fn at_zero(&self, target: &HistoryTarget) -> bool {
+        self.0
+            .iter()
+            .rev()
+            .find(|entry| entry.target == *target)
+            .is_some_and(|entry| entry.value.0 == 0)
+    }
+
+    /// Work out how to revert the most recent mutating action without removing it yet
+    fn last_undo(&self) -> Result<Undo, &'static str> {
+        let (index, entry) = self
+            .0
+            .iter()
+            .enumerate()
+            .rev()
+            .find(|(_, entry)| !matches!(entry.action, CallerAction::Get))
+            .ok_or("Nothing to undo")?;
+
+        let inverse = match &entry.action {
+            CallerAction::Increment => CallerAction::Decrement,
+            CallerAction::Decrement => CallerAction::Increment,
+            CallerAction::Set(_) => {
+                // Any earlier entry on the same counter, including a Get, tells us
+                // what the set overwrote
+                let prior = self.0[..index]
+                    .iter()
+                    .rev()
+                    .find(|prior| prior.target == entry.target)
+                    .ok_or("Can't undo a set without a known prior value")?;
+                CallerAction::Set(prior.value.0)
+            }
+            CallerAction::Batch(_) => return Err("Can't undo a batch"),
+            CallerAction::Get => unreachable!("gets are skipped above"),
+        };
+
+        Ok(Undo {
+            index,
+            undone: entry.action.clone(),
+            expected: entry.value,
+            inverse,
+            target: entry.target.clone(),
+        })
+    }
+
+    fn remove(&mut self, index: usize) {
+        if index < self.0.len() {
+            self.0.remove(index);
+        }
+    }
+}
+
+fn format_time(time: SystemTime) -> String {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default();
+    format!(
+        "{:02}:{:02}:{:02} UTC",
+        secs / 3600 % 24,
+        secs / 60 % 60,
+        secs % 60
+    )
+}
+
+/// ` (1843ms)` for results that know how long they took
+fn format_duration(result: &CallerResult) -> String {
+    result
+        .duration_ms
+        .map(|ms| format!(" ({}ms)", ms))
+        .unwrap_or_default()
+}
+
+/// ` (observed change from 41: +1)` for updates that know the value before them.
+/// Anyone else's update in between is included, so it may not be exactly ±1.
+fn format_change(result: &CallerResult) -> String {
+    result
+        .previous_value
+        .map(|previous| {
+            let change = i128::from(result.value.0) - i128::from(previous.0);
+            format!(" (observed change from {}: {:+})", previous, change)
+        })
+        .unwrap_or_default()
+}
+
+/// User-facing text for a failed call, worded by what the user can do about it
+fn describe_failure(result: &CallerResult) -> String {
+    let error = result.error.as_deref().unwrap_or("unknown error");
+    let description = match result.kind {
+        Some(CallerErrorKind::Transport) => format!(
+            "Couldn't reach the IC for {}: {}. This is usually temporary, try again.",
+            result.action, error
+        ),
+        Some(CallerErrorKind::Reject) => {
+            format!("Canister rejected {}: {}", result.action, error)
+        }
+        Some(CallerErrorKind::Decode) => {
+            format!("Unexpected response for {}: {}", result.action, error)
+        }
+        Some(CallerErrorKind::Timeout) => format!(
+            "{} timed out but may still apply. Use Get to check before retrying.",
+            result.action
+        ),
+        Some(CallerErrorKind::Unavailable) => {
+            format!("The server can't run {}: {}", result.action, error)
+        }
+        None => format!("{} failed: {}", result.action, error),
+    };
+    let request_id = result
+        .request_id
+        .as_ref()
+        .map(|request_id| format!(" Request ID: {}", request_id))
+        .unwrap_or_default();
+    description + &format_duration(result) + &request_id
+}
+
+/// A failed counter action that should replace the buttons with [`CounterErrorBoundary`]'s fallback
+#[derive(Clone, Debug, thiserror::Error)]
+#[error("{0}")]
+struct ActionError(String);
+
+/// Lets actions report failures to the enclosing [`CounterErrorBoundary`].
+/// `ErrorBoundary` only sees errors rendered into the view, so anything raised
+/// from an effect or `spawn_local` has to come through here.
+#[derive(Clone, Copy)]
+struct ActionFailures {
+    error: RwSignal<Option<ActionError>>,
+    last_action: StoredValue<Option<Callback<()>>>,
+}
+
+impl ActionFailures {
+    /// Run `action`, remembering it so "Try again" can run it again
+    fn dispatch(&self, action: Callback<()>) {
+        self.last_action.set_value(Some(action));
+        action.run(());
+    }
+
+    fn report(&self, message: String) {
+        self.error.set(Some(ActionError(message)));
+    }
+
+    /// Clear the error, which resets the boundary
+    fn clear(&self) {
+        self.error.set(None);
+    }
+
+    /// Clear the error, which resets the boundary, and rerun the last action
+    fn retry(&self) {
+        self.clear();
+        if let Some(action) = self.last_action.get_value() {
+            action.run(());
+        }
+    }
+}
+
+/// Shows a friendly message with a "Try again" button in place of its children
+/// when a counter action fails unexpectedly
 #[component]
-fn ServerCallerButtons(set_text: WriteSignal<String>) -> impl IntoView {
-    let action = ServerAction::<ExecuteCallerAction>::new();
+fn CounterErrorBoundary(children: Children) -> impl IntoView {
+    let failures = ActionFailures {
+        error: RwSignal::new(None),
+        last_action: StoredValue::new(None),
+    };
+    provide_context(failures);
+
+    view! {
+        <ErrorBoundary fallback=move |errors| view! {
+            <div class="counter-result result-error action-error">
+                <p>"Something went wrong."</p>
+                <ul>
+                    {move || {
+                        errors
+                            .get()
+                            .into_iter()
+                            .map(|(_, e)| view! { <li>{e.to_string()}</li> })
+                            .collect_view()
+                    }}
+                </ul>
+                <button class="counter-btn retry-btn" on:click=move |_| failures.retry()>
+                    "Try again"
+                </button>
+            </div>
+        }>
+            {move || failures.error.get().map_or(Ok(()), Err)}
+            {children()}
+        </ErrorBoundary>
+    }
+}
+
+/// Repeat presses of the same server button within this window are dropped,
+/// since `pending` only flips once the first dispatch has started
+const DISPATCH_DEBOUNCE: Duration = Duration::from_millis(300);
+
+/// Which button group sent an action, so "Retry last" repeats it the same way
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum CallPath {
+    Server,
+    #[cfg(feature = "client-calls")]
+    Client,
+}
+
+/// The latest action from either button group and whether it failed
+#[derive(Clone, Debug)]
+struct LastAction {
+    path: CallPath,
+    action: CallerAction,
+    failed: bool,
+}
+
+/// Shared by the button groups and [`RetryLast`]. A retry is handed to the group
+/// the action went through via `replay`, since only it can dispatch on its path.
+#[derive(Clone, Copy)]
+struct LastActionState {
+    last: RwSignal<Option<LastAction>>,
+    replay: RwSignal<Option<LastAction>>,
+}
+
+impl LastActionState {
+    fn new() -> Self {
+        Self {
+            last: RwSignal::new(None),
+            replay: RwSignal::new(None),
+        }
+    }
 
+    fn dispatched(&self, path: CallPath, action: CallerAction) {
+        self.last.set(Some(LastAction {
+            path,
+            action,
+            failed: false,
+        }));
+    }
+
+    /// Record how the latest action on `path` went, unless the other group has
+    /// dispatched since
+    fn finished(&self, path: CallPath, failed: bool) {
+        self.last.update(|last| {
+            if let Some(last) = last.as_mut().filter(|last| last.path == path) {
+                last.failed = failed;
+            }
+        });
+    }
+
+    fn can_retry(&self) -> bool {
+        self.last.with(|last| last.as_ref().is_some_and(|last| last.failed))
+    }
+
+    fn retry(&self) {
+        if let Some(last) = self.last.get_untracked().filter(|last| last.failed) {
+            self.replay.set(Some(last));
+        }
+    }
+
+    /// The retry waiting for `path`'s group, taking it so it only runs once
+    fn take_replay(&self, path: CallPath) -> Option<CallerAction> {
+        let replay = self.replay.get().filter(|replay| replay.path == path)?;
+        self.replay.set(None);
+        Some(replay.action)
+    }
+}
+
+/// The key pressed, unless it's a combination or the user is typing somewhere
+fn shortcut_key(ev: &KeyboardEvent) -> Option<String> {
+    if ev.repeat() || ev.ctrl_key() || ev.meta_key() || ev.alt_key() {
+        return None;
+    }
+    let typing = ev
+        .target()
+        .and_then(|target| target.dyn_into::<HtmlElement>().ok())
+        .is_some_and(|element| {
+            matches!(element.tag_name().as_str(), "INPUT" | "TEXTAREA" | "SELECT")
+                || element.is_content_editable()
+        });
+    (!typing).then(|| ev.key())
+}
+
+/// Counter action bound to a key, unless the user is typing somewhere
+fn shortcut_action(ev: &KeyboardEvent) -> Option<CallerAction> {
+    match shortcut_key(ev)?.as_str() {
+        "+" => Some(CallerAction::Increment),
+        "-" => Some(CallerAction::Decrement),
+        "g" => Some(CallerAction::Get),
+        _ => None,
+    }
+}
+
+#[component]
+fn ServerCallerButtons(
+    set_text: WriteSignal<String>,
+    set_error_kind: WriteSignal<Option<CallerErrorKind>>,
+    history: RwSignal<CounterHistory>,
+    counter: RwSignal<Option<String>>,
+    anonymous: RwSignal<Option<bool>>,
+    last_action: LastActionState,
+) -> impl IntoView {
+    let action = ServerAction::<ExecuteCallerAction>::new();
+    let failures = expect_context::<ActionFailures>();
+    // The counter the latest call went to, which the picker may have moved on from
+    let dispatched_to = StoredValue::new(None::<String>);
+    // What the latest call did, for describing its failure
+    let dispatched_action = StoredValue::new(CallerAction::Get);
+    let target = move || HistoryTarget::Server {
+        counter: counter.get(),
+    };
+    // Per action, so increment then decrement in quick succession both go through
+    let last_dispatched =
+        StoredValue::new_local(HashMap::<Discriminant<CallerAction>, Instant>::new());
+    let dispatch = move |caller_action: CallerAction| {
+        let now = Instant::now();
+        let debounced = last_dispatched
+            .try_update_value(|last| {
+                let key = discriminant(&caller_action);
+                if last
+                    .get(&key)
+                    .is_some_and(|at| now.duration_since(*at) < DISPATCH_DEBOUNCE)
+                {
+                    return true;
+                }
+                last.insert(key, now);
+                false
+            })
+            .unwrap_or(true);
+        if debounced {
+            return;
+        }
+        last_action.dispatched(CallPath::Server, caller_action.clone());
+        failures.dispatch(Callback::new(move |_| {
+            dispatched_to.set_value(counter.get_untracked());
+            dispatched_action.set_value(caller_action.clone());
+            action.dispatch(ExecuteCallerAction {
+                action: caller_action.clone(),
+                counter: counter.get_untracked(),
+            });
+        }));
+    };
     Effect::new(move || {
         if let Some(result) = action.value().get() {
             match result {
                 Ok(counter_result) => {
-                    if counter_result.success {
-                        set_text(format!("Current Value: {}", counter_result.value))
-                    } else {
-                        set_text(format!("{:#?}", counter_result))
+                    set_error_kind(None);
+                    anonymous.set(Some(counter_result.anonymous));
+                    last_action.finished(CallPath::Server, false);
+                    let counter = dispatched_to.get_value();
+                    save_cached_value(counter.as_deref(), counter_result.value);
+                    history.update(|history| {
+                        history.record(
+                            HistoryTarget::Server { counter },
+                            counter_result.action.clone(),
+                            counter_result.value,
+                        )
+                    });
+                    set_text(format!(
+                        "Current Value: {}{}{}",
+                        counter_result.value,
+                        format_change(&counter_result),
+                        format_duration(&counter_result)
+                    ))
+                }
+                Err(e) if e.call_made() => {
+                    last_action.finished(CallPath::Server, true);
+                    set_error_kind(Some(e.kind()));
+                    set_text(describe_failure(&CallerResult::from_error(
+                        dispatched_action.get_value(),
+                        &e,
+                    )))
+                }
+                // No call was made, e.g. the request never reached the server or
+                // named an unknown counter
+                Err(e) => {
+                    last_action.finished(CallPath::Server, true);
+                    match e.kind() {
+                        // Worth another go, which the error boundary offers
+                        CallerErrorKind::Transport | CallerErrorKind::Timeout => {
+                            failures.report(format!("Server Error: {}", e))
+                        }
+                        kind @ (CallerErrorKind::Reject
+                        | CallerErrorKind::Decode
+                        | CallerErrorKind::Unavailable) => {
+                            set_error_kind(Some(kind));
+                            set_text(format!("Server Error: {}", e))
+                        }
                     }
                 }
-                Err(e) => set_text(format!("Server Error: {}", e)),
             }
         } else {
-            set_text("Click Get to retrieve value".to_string())
+            // Effects only run in the browser after hydration, so the server's
+            // placeholder and the first client render still agree
+            set_text(match load_cached_value() {
+                Some(value) => format!("Current Value: {} (cached)", value),
+                None => "Click Get to retrieve value".to_string(),
+            })
+        }
+    });
+    // A cached value may be stale, so read the real one straight away
+    Effect::new(move |_| {
+        if load_cached_value().is_some() {
+            dispatch(CallerAction::Get);
+        }
+    });
+    Effect::new(move || {
+        if let Some(caller_action) = last_action.take_replay(CallPath::Server) {
+            failures.clear();
+            dispatch(caller_action);
+        }
+    });
+
+    // Shortcuts obey the same rules as the buttons they stand in for
+    let shortcuts = window_event_listener(leptos::ev::keydown, move |ev| {
+        let Some(caller_action) = shortcut_action(&ev) else {
+            return;
+        };
+        let at_zero = history.with_untracked(|history| {
+            history.at_zero(&HistoryTarget::Server {
+                counter: counter.get_untracked(),
+            })
+        });
+        if action.pending().get_untracked()
+            || (matches!(caller_action, CallerAction::Decrement) && at_zero)
+        {
+            return;
         }
+        ev.prevent_default();
+        dispatch(caller_action);
     });
+    on_cleanup(move || shortcuts.remove());
 
     view! {
         <div class="button-group">
         <h3>"Server-Side Buttons"</h3>
             <button
                 class="counter-btn get-btn"
-                on:click=move |_| {
-                    action.dispatch(ExecuteCallerAction {
-                        action: CallerAction::Get
-                    });
-                }
+                on:click=move |_| dispatch(CallerAction::Get)
                 disabled=move || action.pending().get()
+                aria-busy=move || action.pending().get().to_string()
+                aria-label="Get counter via server"
+                aria-keyshortcuts="g"
             >
-                 "Server Get"
+                 "Server Get" <kbd>"g"</kbd>
             </button>
 
             <button
                 class="counter-btn increment-btn"
-                on:click=move |_| {
-                    action.dispatch(ExecuteCallerAction {
-                        action: CallerAction::Increment
-                    });
-                }
+                on:click=move |_| dispatch(CallerAction::Increment)
                 disabled=move || action.pending().get()
+                aria-busy=move || action.pending().get().to_string()
+                aria-label="Increment counter via server"
+                aria-keyshortcuts="+"
             >
-                "Server Increment"
+                "Server Increment" <kbd>"+"</kbd>
             </button>
 
             <button
                 class="counter-btn decrement-btn"
-                on:click=move |_| {
-                    action.dispatch(ExecuteCallerAction {
-                        action: CallerAction::Decrement
-                    });
-                }
-                disabled=move || action.pending().get()
+                on:click=move |_| dispatch(CallerAction::Decrement)
+                disabled=move || action.pending().get() || history.with(|history| history.at_zero(&target()))
+                aria-busy=move || action.pending().get().to_string()
+                aria-label="Decrement counter via server"
+                aria-keyshortcuts="-"
             >
-                "Server Decrement"
+                "Server Decrement" <kbd>"-"</kbd>
             </button>
         </div>
     }
 }
 
+#[cfg(feature = "client-calls")]
 #[component]
-fn ClientCallerButtons(set_text: WriteSignal<String>) -> impl IntoView {
+fn ClientCallerButtons(
+    set_text: WriteSignal<String>,
+    set_error_kind: WriteSignal<Option<CallerErrorKind>>,
+    history: RwSignal<CounterHistory>,
+    last_action: LastActionState,
+) -> impl IntoView {
     // Get the ICClient signal from context
     let ic_client_signal = use_context::<ReadSignal<Option<ICClient>>>();
+    // Why the client couldn't be created, instead of loading forever
+    let client_error = use_context::<ReadSignal<Option<String>>>();
+    let failures = expect_context::<ActionFailures>();
+    let in_flight = InFlightCall::new(last_action);
+    // Nobody is left to show the result to
+    on_cleanup(move || in_flight.abort());
+    let dispatch = move |ic_client: ICClient, caller_action: CallerAction| {
+        last_action.dispatched(CallPath::Client, caller_action.clone());
+        failures.dispatch(Callback::new(move |_| {
+            spawn_client_action(
+                ic_client.clone(),
+                caller_action.clone(),
+                set_text,
+                set_error_kind,
+                history,
+                failures,
+                in_flight,
+            )
+        }));
+    };
+    Effect::new(move || {
+        if let Some(caller_action) = last_action.take_replay(CallPath::Client) {
+            if let Some(ic_client) = ic_client_signal.and_then(|sig| sig.get_untracked()) {
+                failures.clear();
+                dispatch(ic_client, caller_action);
+            }
+        }
+    });
 
     view! {
         <div class="button-group client-buttons">
             <h3>"Client-Side Buttons"</h3>
             <Show
                 when=move || ic_client_signal.map(|sig| sig.get().is_some()).unwrap_or(false)
-                fallback=move || view! {
-                    <button class="counter-btn get-btn" disabled=true>"Client Get (Loading...)"</button>
-                    <button class="counter-btn increment-btn" disabled=true>"Client Increment (Loading...)"</button>
-                    <button class="counter-btn decrement-btn" disabled=true>"Client Decrement (Loading...)"</button>
+                fallback=move || match client_error.and_then(|sig| sig.get()) {
+                    Some(error) => view! {
+                        <p class="counter-result result-error" role="alert">{error}</p>
+                    }.into_any(),
+                    None => view! {
+                        <button class="counter-btn get-btn" disabled=true aria-busy="true" aria-label="Get counter via browser, loading">"Client Get (Loading...)"</button>
+                        <button class="counter-btn increment-btn" disabled=true aria-busy="true" aria-label="Increment counter via browser, loading">"Client Increment (Loading...)"</button>
+                        <button class="counter-btn decrement-btn" disabled=true aria-busy="true" aria-label="Decrement counter via browser, loading">"Client Decrement (Loading...)"</button>
+                    }.into_any(),
                 }
             >
                 {move || {
                     let ic_client = ic_client_signal.unwrap().get().unwrap();
+                    let target = HistoryTarget::Client {
+                        env: ic_client.deployment_env(),
+                    };
+                    let dispatch = move |caller_action: CallerAction| {
+                        dispatch(ic_client.clone(), caller_action)
+                    };
                     view! {
                         <button
                             class="counter-btn get-btn"
                             on:click={
-                                let ic_client = ic_client.clone();
-                                move |_| {
-                                    let ic_client = ic_client.clone();
-                                    spawn_local(async move {
-                                        match ic_client.caller_get().await {
-                                            Ok(value) =>{
-                                                set_text(format!("Current Value: {}", value))
-                                            },
-                                            Err(e) => set_text(format!("Client Error: {}", e)),
-                                        }
-                                    });
-                                }
+                                let dispatch = dispatch.clone();
+                                move |_| dispatch(CallerAction::Get)
                             }
+                            aria-label="Get counter via browser"
                         >
                             "Client Get"
                         </button>
@@ -131,39 +729,19 @@ fn ClientCallerButtons(set_text: WriteSignal<String>) -> impl IntoView {
                         <button
                             class="counter-btn increment-btn"
                             on:click={
-                                let ic_client = ic_client.clone();
-                                move |_| {
-                                    let ic_client = ic_client.clone();
-                                    spawn_local(async move {
-                                        match ic_client.caller_increment().await {
-                                            Ok(value) =>{
-                                                set_text(format!("Current Value: {}", value))
-                                            },
-                                            Err(e) => set_text(format!("Client Error: {}", e)),
-                                        }
-                                    });
-                                }
+                                let dispatch = dispatch.clone();
+                                move |_| dispatch(CallerAction::Increment)
                             }
+                            aria-label="Increment counter via browser"
                         >
                             "Client Increment"
                         </button>
 
                         <button
                             class="counter-btn decrement-btn"
-                            on:click={
-                                let ic_client = ic_client.clone();
-                                move |_| {
-                                    let ic_client = ic_client.clone();
-                                    spawn_local(async move {
-                                        match ic_client.caller_decrement().await {
-                                            Ok(value) =>{
-                                                set_text(format!("Current Value: {}", value))
-                                            },
-                                            Err(e) => set_text(format!("Client Error: {}", e)),
-                                        }
-                                    });
-                                }
-                            }
+                            on:click=move |_| dispatch(CallerAction::Decrement)
+                            disabled=move || history.with(|history| history.at_zero(&target))
+                            aria-label="Decrement counter via browser"
                         >
                             "Client Decrement"
                         </button>
@@ -174,23 +752,395 @@ fn ClientCallerButtons(set_text: WriteSignal<String>) -> impl IntoView {
     }
 }
 
+/// The browser-side call whose result will be shown, so a newer one can abort it
+#[cfg(feature = "client-calls")]
+#[derive(Clone, Copy)]
+struct InFlightCall {
+    calls: StoredValue<(u64, Option<RunningCall>)>,
+    last_action: LastActionState,
+}
+
+/// Id, abort handle and action of the browser-side call that is running
+#[cfg(feature = "client-calls")]
+type RunningCall = (u64, AbortHandle, CallerAction);
+
+#[cfg(feature = "client-calls")]
+impl InFlightCall {
+    fn new(last_action: LastActionState) -> Self {
+        Self {
+            calls: StoredValue::new((0, None)),
+            last_action,
+        }
+    }
+
+    /// Abort the running call, if any, in favour of `action`. Returns the new
+    /// call's id and a note for the user when the aborted call was an update:
+    /// it may already have reached the canister, and aborting can't undo that.
+    fn replace(&self, handle: AbortHandle, action: CallerAction) -> (u64, String) {
+        self.calls
+            .try_update_value(|(next_id, current)| {
+                let note = match current.take() {
+                    Some((_, previous, previous_action)) => {
+                        previous.abort();
+                        match previous_action {
+                            CallerAction::Get => String::new(),
+                            previous_action => format!(
+                                " (previous {} was abandoned but may still have applied)",
+                                previous_action
+                            ),
+                        }
+                    }
+                    None => String::new(),
+                };
+                *next_id += 1;
+                *current = Some((*next_id, handle, action));
+                (*next_id, note)
+            })
+            .unwrap_or_default()
+    }
+
+    /// Forget call `id` once it has finished, unless a newer call replaced it,
+    /// and record whether it failed for "Retry last"
+    fn finish(&self, id: u64, failed: bool) {
+        self.calls.try_update_value(|(_, current)| {
+            if current
+                .as_ref()
+                .is_some_and(|(current_id, ..)| *current_id == id)
+            {
+                *current = None;
+            }
+        });
+        self.last_action.finished(CallPath::Client, failed);
+    }
+
+    fn abort(&self) {
+        self.calls.try_update_value(|(_, current)| {
+            if let Some((_, handle, _)) = current.take() {
+                handle.abort();
+            }
+        });
+    }
+}
+
+/// Call the canister from the browser, abandoning any call still running so only
+/// the latest action's result is shown. Errors raised inside `spawn_local` never
+/// reach an `ErrorBoundary` on their own, so they are reported to `failures`.
+#[cfg(feature = "client-calls")]
+fn spawn_client_action(
+    ic_client: ICClient,
+    action: CallerAction,
+    set_text: WriteSignal<String>,
+    set_error_kind: WriteSignal<Option<CallerErrorKind>>,
+    history: RwSignal<CounterHistory>,
+    failures: ActionFailures,
+    in_flight: InFlightCall,
+) {
+    let target = HistoryTarget::Client {
+        env: ic_client.deployment_env(),
+    };
+    let (call, handle) = abortable({
+        let action = action.clone();
+        async move {
+            let previous_value = ic_client.value_before(&action).await;
+            let started = web_time::Instant::now();
+            // Bounded by CLIENT_CALL_TIMEOUT, so this always comes back
+            let result = ic_client.caller_action(&action).await;
+            CallerResult::from_call(action, result)
+                .with_duration(started.elapsed())
+                .with_previous_value(previous_value)
+                .with_anonymous(ic_client.is_anonymous())
+        }
+    });
+    let (id, note) = in_flight.replace(handle, action);
+    spawn_local(async move {
+        // Aborted by a newer click, which reports instead
+        let Ok(result) = call.await else {
+            return;
+        };
+        in_flight.finish(id, !result.success);
+        if result.success {
+            set_error_kind(None);
+            save_target_value(&target, result.value);
+            history.update(|history| history.record(target, result.action.clone(), result.value));
+            set_text(format!(
+                "Current Value: {}{}{}{}",
+                result.value,
+                format_change(&result),
+                format_duration(&result),
+                note
+            ))
+        } else {
+            failures.report(format!(
+                "Client Error: {}{}",
+                describe_failure(&result),
+                note
+            ))
+        }
+    });
+}
+
+/// Open the server's live counter stream, reopening it after `RECONNECT_DELAY`
+/// whenever the browser gives up on it
+fn subscribe_counter_stream(
+    source: StoredValue<Option<EventSource>, LocalStorage>,
+    live_value: RwSignal<Option<String>>,
+) {
+    // The component unmounted while a reconnect was pending
+    if source.try_with_value(|_| ()).is_none() {
+        return;
+    }
+    let event_source = match EventSource::new(COUNTER_STREAM_PATH) {
+        Ok(event_source) => event_source,
+        Err(e) => {
+            leptos::logging::warn!("Failed to open counter stream: {:?}", e);
+            return;
+        }
+    };
+
+    let on_value = Closure::<dyn Fn(MessageEvent)>::new(move |event: MessageEvent| {
+        if let Some(value) = event.data().as_string() {
+            // A reconnect replays the last value, don't re-render for it
+            if live_value.get_untracked().as_deref() != Some(value.as_str()) {
+                live_value.set(Some(value));
+            }
+        }
+    });
+    _ = event_source.add_event_listener_with_callback("value", on_value.as_ref().unchecked_ref());
+    on_value.forget();
+
+    let on_error = Closure::<dyn Fn()>::new(move || {
+        // The browser retries dropped connections by itself unless it closed the stream
+        let closed = source
+            .try_with_value(|source| {
+                source
+                    .as_ref()
+                    .is_some_and(|source| source.ready_state() == EventSource::CLOSED)
+            })
+            .unwrap_or(false);
+        if closed {
+            set_timeout(
+                move || subscribe_counter_stream(source, live_value),
+                RECONNECT_DELAY,
+            );
+        }
+    });
+    event_source.set_onerror(Some(on_error.as_ref().unchecked_ref()));
+    on_error.forget();
+
+    source.set_value(Some(event_source));
+}
+
 #[component]
-pub fn App() -> impl IntoView {
-    provide_meta_context();
+fn LiveCounterValue() -> impl IntoView {
+    let live_value = RwSignal::new(None::<String>);
+    let source = StoredValue::new_local(None::<EventSource>);
+
+    Effect::new(move || subscribe_counter_stream(source, live_value));
+    on_cleanup(move || {
+        if let Some(Some(source)) = source.try_update_value(|source| source.take()) {
+            source.close();
+        }
+    });
+
+    view! {
+        <p class="live-counter">
+            {move || match live_value.get() {
+                Some(value) => format!("Live Value: {}", value),
+                None => "Live Value: waiting for updates...".to_string(),
+            }}
+        </p>
+        <ThresholdAlert live_value/>
+    }
+}
 
-    let (ic_client, set_ic_client) = signal::<Option<ICClient>>(None);
+/// "Notify me at N": alerts the first time the live value reaches N, then
+/// disarms, so a decrement back below and up again doesn't alert twice
+#[component]
+fn ThresholdAlert(live_value: RwSignal<Option<String>>) -> impl IntoView {
+    let draft = RwSignal::new(String::new());
+    let threshold = RwSignal::new(ThresholdWatch::default());
 
     Effect::new(move || {
-        let config = ICConfig::default_mainnet();
-        leptos::logging::log!("Config is {:?}", config);
-        spawn_local(async move {
-            match create_client_from_config(&config).await {
-                Ok(client) => set_ic_client(Some(client)),
-                Err(e) => panic!("Failed to create client: {}", e),
+        let mut watch = threshold.get();
+        let Some(target) = watch.armed() else {
+            return;
+        };
+        let reached = live_value
+            .with(|value| value.as_deref().and_then(|value| value.parse::<u64>().ok()))
+            .map(CounterValue)
+            .filter(|value| watch.observe(*value));
+        if let Some(value) = reached {
+            threshold.set(watch);
+            if let Some(window) = web_sys::window() {
+                _ = window.alert_with_message(&format!(
+                    "The counter reached {} (you asked to be told at {})",
+                    value, target
+                ));
             }
-        });
+        }
     });
 
+    view! {
+        <div class="threshold-alert">
+            <Show
+                when=move || threshold.get().armed().is_some()
+                fallback=move || {
+                    view! {
+                        <label>
+                            "Notify me at "
+                            <input
+                                type="number"
+                                min="0"
+                                prop:value=move || draft.get()
+                                on:input=move |ev| draft.set(event_target_value(&ev))
+                            />
+                        </label>
+                        <button
+                            class="counter-btn"
+                            disabled=move || draft.with(|draft| draft.trim().parse::<u64>().is_err())
+                            on:click=move |_| {
+                                if let Ok(target) = draft.with(|draft| draft.trim().parse()) {
+                                    threshold.set(ThresholdWatch::new(CounterValue(target)));
+                                }
+                            }
+                        >
+                            "Notify me"
+                        </button>
+                    }
+                }
+            >
+                <span>{move || format!("Watching for {}", threshold.get().armed().unwrap_or_default())}</span>
+                <button class="counter-btn" on:click=move |_| threshold.set(ThresholdWatch::default())>
+                    "Cancel"
+                </button>
+            </Show>
+        </div>
+    }
+}
+
+/// State of the `ICClient` the browser uses to call the canisters itself
+#[cfg(feature = "client-calls")]
+#[derive(Clone, Copy)]
+struct BrowserClient {
+    ic_client: ReadSignal<Option<ICClient>>,
+    set_ic_client: WriteSignal<Option<ICClient>>,
+    client_error: ReadSignal<Option<String>>,
+    set_client_error: WriteSignal<Option<String>>,
+    deployment_env: RwSignal<String>,
+}
+
+#[cfg(feature = "client-calls")]
+impl BrowserClient {
+    /// (Re)build the client whenever the selected deployment environment changes
+    fn spawn() -> Self {
+        let (ic_client, set_ic_client) = signal::<Option<ICClient>>(None);
+        let (client_error, set_client_error) = signal(None::<String>);
+        let deployment_env = RwSignal::new(DEFAULT_DEPLOYMENT_ENV.to_string());
+
+        Effect::new(move |restored: Option<()>| {
+            // Effects only run in the browser after hydration, so the server and the
+            // first client render both use the default and can't disagree
+            if restored.is_none() {
+                if let Some(env) = load_deployment_env() {
+                    deployment_env.set(env);
+                }
+            }
+            let env = deployment_env.get();
+            // Show the buttons as loading until the new target is connected, releasing
+            // the old target's connections
+            set_ic_client.update(|client| {
+                if let Some(mut client) = client.take() {
+                    client.close();
+                }
+            });
+            let config = match ICConfig::default_for(&env) {
+                Ok(config) => config,
+                Err(e) => {
+                    set_client_error(Some(format!("Failed to create client: {}", e)));
+                    return;
+                }
+            };
+            set_client_error(None);
+            spawn_local(async move {
+                match Self::connect(&config).await {
+                    // A later switch superseded this one
+                    _ if deployment_env.get_untracked() != env => {}
+                    Ok(client) => set_ic_client(Some(client)),
+                    Err(e) => {
+                        leptos::logging::warn!("Failed to create client: {}", e);
+                        set_client_error(Some(e.to_string()))
+                    }
+                }
+            });
+        });
+
+        Self {
+            ic_client,
+            set_ic_client,
+            client_error,
+            set_client_error,
+            deployment_env,
+        }
+    }
+
+    /// Build a client for `config`, signing as a previous Internet Identity login
+    /// if there is one and anonymously otherwise
+    async fn connect(config: &ICConfig) -> anyhow::Result<ICClient> {
+        let client = create_client_from_config(config)
+            .await?
+            .with_call_timeout(CLIENT_CALL_TIMEOUT);
+        Ok(match auth::restore_session().await {
+            Ok(Some(identity)) => client.with_identity(identity),
+            Ok(None) => client,
+            Err(e) => {
+                leptos::logging::warn!("Failed to restore login: {}", e);
+                client
+            }
+        })
+    }
+
+    /// Replace the client for the current environment with a fresh one after it
+    /// stopped answering. Returns whether the new client was installed.
+    async fn reconnect(self) -> bool {
+        let env = self.deployment_env.get_untracked();
+        let Ok(config) = ICConfig::default_for(&env) else {
+            return false;
+        };
+        match Self::connect(&config).await {
+            // The user switched environments meanwhile, which rebuilds the client anyway
+            _ if self.deployment_env.get_untracked() != env => false,
+            Ok(client) => {
+                self.set_ic_client.update(|current| {
+                    if let Some(mut stale) = current.replace(client) {
+                        stale.close();
+                    }
+                });
+                self.set_client_error.set(None);
+                true
+            }
+            Err(e) => {
+                leptos::logging::warn!("Failed to reconnect client: {}", e);
+                false
+            }
+        }
+    }
+
+    fn provide(self) {
+        provide_context(self);
+        provide_context(self.ic_client);
+        provide_context(self.set_ic_client);
+        provide_context(self.client_error);
+        provide_context(self.deployment_env);
+    }
+}
+
+#[component]
+pub fn App() -> impl IntoView {
+    provide_meta_context();
+
+    #[cfg(feature = "client-calls")]
+    let browser_client = BrowserClient::spawn();
+
     view! {
         // injects a stylesheet into the document <head>
         // id=leptos means cargo-leptos will hot-reload this stylesheet
@@ -205,7 +1155,8 @@ pub fn App() -> impl IntoView {
                 <Routes fallback=|| "Page not found.".into_view()>
                     <Route path=StaticSegment("") view=move || {
                         // Always provide the ICClient signal context for consistent hydration
-                        provide_context(ic_client);
+                        #[cfg(feature = "client-calls")]
+                        browser_client.provide();
                         view! { <HomePage/> }
                     }/>
                 </Routes>
@@ -214,20 +1165,690 @@ pub fn App() -> impl IntoView {
     }
 }
 
+/// Picks which environment the browser-side buttons call
+#[cfg(feature = "client-calls")]
+#[component]
+fn EnvironmentSwitcher() -> impl IntoView {
+    let deployment_env = expect_context::<RwSignal<String>>();
+
+    view! {
+        <label class="env-switcher">
+            "Browser target "
+            <select
+                prop:value=move || deployment_env.get()
+                on:change=move |ev| {
+                    let env = event_target_value(&ev);
+                    save_deployment_env(&env);
+                    deployment_env.set(env);
+                }
+            >
+                {DEPLOYMENT_ENVS
+                    .iter()
+                    .map(|env| {
+                        // Also set on the options so the server-rendered page shows the default
+                        view! {
+                            <option value=*env selected=move || deployment_env.get() == *env>
+                                {*env}
+                            </option>
+                        }
+                    })
+                    .collect_view()}
+            </select>
+        </label>
+    }
+}
+
+/// How the browser-side client's replica last responded
+#[cfg(feature = "client-calls")]
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum ConnectionHealth {
+    Up,
+    /// Not checked yet, still connecting, or reconnected but not pinged since
+    Unknown,
+    Down,
+}
+
+#[cfg(feature = "client-calls")]
+impl ConnectionHealth {
+    fn label(self) -> &'static str {
+        match self {
+            ConnectionHealth::Up => "Connected",
+            ConnectionHealth::Unknown => "Connecting...",
+            ConnectionHealth::Down => "Disconnected",
+        }
+    }
+
+    fn class(self) -> &'static str {
+        match self {
+            ConnectionHealth::Up => "connection-status connection-up",
+            ConnectionHealth::Unknown => "connection-status connection-unknown",
+            ConnectionHealth::Down => "connection-status connection-down",
+        }
+    }
+}
+
+/// Signals behind [`ConnectionStatus`], plus the wait before the next reconnect
+#[cfg(feature = "client-calls")]
+#[derive(Clone, Copy)]
+struct ConnectionMonitor {
+    browser_client: BrowserClient,
+    health: RwSignal<ConnectionHealth>,
+    last_ping: RwSignal<Option<SystemTime>>,
+    backoff: StoredValue<Duration>,
+}
+
+#[cfg(feature = "client-calls")]
+impl ConnectionMonitor {
+    /// Check the connection after `delay`, then keep rescheduling until unmounted
+    fn schedule(self, delay: Duration) {
+        set_timeout(
+            move || {
+                // The component unmounted while the check was pending
+                if self.health.try_get_untracked().is_none() {
+                    return;
+                }
+                spawn_local(async move {
+                    let next = self.check().await;
+                    self.schedule(next);
+                });
+            },
+            delay,
+        );
+    }
+
+    /// Ping the replica, rebuilding the client if it doesn't answer. Returns how
+    /// long to wait before checking again, doubling while reconnects don't help.
+    async fn check(self) -> Duration {
+        let client = self.browser_client.ic_client.get_untracked();
+        let answered = match client {
+            Some(client) => client.ping().await.is_ok(),
+            // Still connecting, e.g. right after an environment switch
+            None if self.browser_client.client_error.get_untracked().is_none() => {
+                self.health.try_set(ConnectionHealth::Unknown);
+                return PING_INTERVAL;
+            }
+            None => false,
+        };
+        if answered {
+            self.health.try_set(ConnectionHealth::Up);
+            self.last_ping.try_set(Some(SystemTime::now()));
+            self.backoff.try_set_value(PING_INTERVAL);
+            return PING_INTERVAL;
+        }
+
+        // Building a client for mainnet never touches the network, so even a
+        // successful rebuild backs off until a ping gets through
+        let reconnected = self.browser_client.reconnect().await;
+        self.health.try_set(if reconnected {
+            ConnectionHealth::Unknown
+        } else {
+            ConnectionHealth::Down
+        });
+        let backoff = self.backoff.try_get_value().unwrap_or(PING_INTERVAL);
+        self.backoff
+            .try_set_value((backoff * 2).min(MAX_RECONNECT_BACKOFF));
+        backoff
+    }
+}
+
+/// Dot showing whether the browser-side client's replica answers, rebuilding the
+/// client with exponential backoff while it doesn't
+#[cfg(feature = "client-calls")]
+#[component]
+fn ConnectionStatus() -> impl IntoView {
+    let monitor = ConnectionMonitor {
+        browser_client: expect_context::<BrowserClient>(),
+        health: RwSignal::new(ConnectionHealth::Unknown),
+        last_ping: RwSignal::new(None),
+        backoff: StoredValue::new(PING_INTERVAL),
+    };
+    // Effects only run in the browser, so the server never pings
+    Effect::new(move || monitor.schedule(Duration::ZERO));
+
+    view! {
+        <span
+            class=move || monitor.health.get().class()
+            title=move || match monitor.last_ping.get() {
+                Some(time) => format!("Last successful ping: {}", format_time(time)),
+                None => "No successful ping yet".to_string(),
+            }
+        >
+            <span class="connection-dot"></span>
+            {move || monitor.health.get().label()}
+        </span>
+    }
+}
+
+#[cfg(feature = "client-calls")]
+#[component]
+fn AuthControls(set_text: WriteSignal<String>) -> impl IntoView {
+    let ic_client_signal = use_context::<ReadSignal<Option<ICClient>>>();
+    let set_ic_client = use_context::<WriteSignal<Option<ICClient>>>();
+
+    let principal = move || {
+        ic_client_signal
+            .and_then(|sig| sig.get())
+            .and_then(|client| client.get_principal().ok())
+    };
+    let is_anonymous =
+        move || principal().is_none_or(|principal| principal == Principal::anonymous());
+
+    // Swap the identity on the existing client rather than reconnecting
+    let replace_identity = move |identity: Arc<dyn ic_agent::Identity>| {
+        if let (Some(ic_client_signal), Some(set_ic_client)) = (ic_client_signal, set_ic_client) {
+            let client = ic_client_signal.get_untracked();
+            set_ic_client(client.map(|client| client.with_identity(identity)));
+        }
+    };
+
+    let login = move |_| {
+        spawn_local(async move {
+            match auth::login().await {
+                Ok(identity) => replace_identity(identity),
+                Err(e) => set_text(format!("Login failed: {}", e)),
+            }
+        });
+    };
+    let logout = move |_| {
+        spawn_local(async move {
+            if let Err(e) = auth::logout().await {
+                set_text(format!("Logout failed: {}", e));
+            }
+            replace_identity(Arc::new(AnonymousIdentity));
+        });
+    };
+
+    view! {
+        <div class="auth-controls">
+            <Show
+                when=is_anonymous
+                fallback=move || view! {
+                    <span class="auth-principal">
+                        "Logged in as " {move || principal().map(|p| p.to_text()).unwrap_or_default()}
+                    </span>
+                    <button class="auth-btn" on:click=logout>"Logout"</button>
+                }
+            >
+                <span class="auth-principal">"Calling anonymously"</span>
+                <button
+                    class="auth-btn"
+                    on:click=login
+                    disabled=move || ic_client_signal.map(|sig| sig.get().is_none()).unwrap_or(true)
+                >
+                    "Login with Internet Identity"
+                </button>
+            </Show>
+        </div>
+    }
+}
+
+/// How to reach a history entry's counter again, see [`HistoryTarget`]
+#[derive(Clone)]
+enum UndoPath {
+    Server {
+        counter: Option<String>,
+    },
+    #[cfg(feature = "client-calls")]
+    Client(Box<ICClient>),
+}
+
+impl UndoPath {
+    /// The way back to `target`. The browser client only reaches the environment
+    /// it is switched to, so entries from another one can't be undone until the
+    /// user switches back.
+    fn to(target: &HistoryTarget) -> Result<Self, String> {
+        match target {
+            HistoryTarget::Server { counter } => Ok(UndoPath::Server {
+                counter: counter.clone(),
+            }),
+            #[cfg(feature = "client-calls")]
+            HistoryTarget::Client { env } => use_context::<ReadSignal<Option<ICClient>>>()
+                .and_then(|client| client.get_untracked())
+                .filter(|client| client.deployment_env() == *env)
+                .map(|client| UndoPath::Client(Box::new(client)))
+                .ok_or_else(|| format!("Switch the browser client back to {} to undo this", env)),
+        }
+    }
+
+    async fn call(&self, action: CallerAction) -> Result<CallerResult, String> {
+        match self {
+            UndoPath::Server { counter } => execute_counter_action(action.clone(), counter.clone())
+                .await
+                .map_err(|e| describe_failure(&CallerResult::from_error(action, &e))),
+            #[cfg(feature = "client-calls")]
+            UndoPath::Client(client) => {
+                let result = client.caller_action(&action).await;
+                Ok(CallerResult::from_call(action, result))
+            }
+        }
+    }
+}
+
+#[component]
+fn HistoryLog(history: RwSignal<CounterHistory>, set_text: WriteSignal<String>) -> impl IntoView {
+    let (undoing, set_undoing) = signal(false);
+
+    let undo_last = move |_| {
+        let undo = match history.with_untracked(|history| history.last_undo()) {
+            Ok(undo) => undo,
+            Err(reason) => {
+                set_text(reason.to_string());
+                return;
+            }
+        };
+        let path = match UndoPath::to(&undo.target) {
+            Ok(path) => path,
+            Err(reason) => {
+                set_text(reason);
+                return;
+            }
+        };
+        set_undoing(true);
+        spawn_local(async move {
+            // The canister is shared, so check nobody moved it since our action
+            let drift = match path.call(CallerAction::Get).await {
+                Ok(current) if !current.success => {
+                    set_text(format!("Undo failed: {}", describe_failure(&current)));
+                    set_undoing(false);
+                    return;
+                }
+                Ok(current) if current.value != undo.expected => format!(
+                    " (warning: expected {} before undo but the counter was {}, it was changed elsewhere)",
+                    undo.expected, current.value
+                ),
+                Ok(_) => String::new(),
+                Err(e) => {
+                    set_text(format!("Undo failed: {}", e));
+                    set_undoing(false);
+                    return;
+                }
+            };
+            match path.call(undo.inverse).await {
+                Ok(result) if !result.success => {
+                    set_text(format!("Undo failed: {}", describe_failure(&result)))
+                }
+                Ok(result) => {
+                    save_target_value(&undo.target, result.value);
+                    history.update(|history| history.remove(undo.index));
+                    set_text(format!(
+                        "Undid {}: Current Value: {}{}",
+                        undo.undone, result.value, drift
+                    ));
+                }
+                Err(e) => set_text(format!("Undo failed: {}", e)),
+            }
+            set_undoing(false);
+        });
+    };
+
+    view! {
+        <div class="history">
+            <button
+                class="counter-btn undo-btn"
+                on:click=undo_last
+                disabled=move || undoing.get() || history.with(|history| history.last_undo().is_err())
+                aria-busy=move || undoing.get().to_string()
+                aria-label="Undo last counter change"
+            >
+                "Undo last"
+            </button>
+            <ol class="history-entries">
+                {move || {
+                    history
+                        .with(|history| {
+                            history
+                                .entries()
+                                .iter()
+                                .rev()
+                                .map(|entry| {
+                                    view! {
+                                        <li>
+                                            {format!(
+                                                "{} → {} ({}, {})",
+                                                entry.action,
+                                                entry.value,
+                                                entry.target,
+                                                format_time(entry.at)
+                                            )}
+                                        </li>
+                                    }
+                                })
+                                .collect_view()
+                        })
+                }}
+            </ol>
+        </div>
+    }
+}
+
+/// Shorten a principal to its first group, e.g. `qmgff-…`
+fn short_principal(principal: &str) -> String {
+    match principal.split_once('-') {
+        Some((head, _)) => format!("{}-…", head),
+        None => principal.to_string(),
+    }
+}
+
+/// Says which environment the server-side buttons hit, loudly when it's mainnet
+#[component]
+fn DeploymentBanner(
+    info: OnceResource<Result<DeploymentInfo, ServerFnError<String>>>,
+) -> impl IntoView {
+    view! {
+        <Suspense>
+            {move || {
+                info.get()
+                    .and_then(Result::ok)
+                    .map(|DeploymentInfo { env, counter_canister_id, caller_canister_id, dry_run, .. }| {
+                        view! {
+                            <p
+                                class=format!("deployment-banner env-{}", env)
+                                title=format!("counter {}, caller {}", counter_canister_id, caller_canister_id)
+                            >
+                                {format!(
+                                    "Connected to: {} — {}",
+                                    env,
+                                    short_principal(&counter_canister_id)
+                                )}
+                                <Show when=move || dry_run>
+                                    <span class="dry-run-badge">"DRY RUN"</span>
+                                </Show>
+                            </p>
+                        }
+                    })
+            }}
+        </Suspense>
+    }
+}
+
+/// Warns that the server-side buttons change the counter without an identity.
+/// Goes by the latest call, or what the server reported at load before any.
+#[component]
+fn AnonymousWarning(
+    info: OnceResource<Result<DeploymentInfo, ServerFnError<String>>>,
+    anonymous: RwSignal<Option<bool>>,
+) -> impl IntoView {
+    let show = move || {
+        anonymous.get().unwrap_or_else(|| {
+            info.get()
+                .and_then(Result::ok)
+                .is_some_and(|info| info.anonymous)
+        })
+    };
+    view! {
+        <Suspense>
+            <Show when=show>
+                <p class="anonymous-warning" role="alert">
+                    "You are calling anonymously — state changes are not attributable."
+                </p>
+            </Show>
+        </Suspense>
+    }
+}
+
+/// Picks which of the server's named counters the server-side buttons operate
+/// on. Hidden when the server only knows the default counter.
+#[component]
+fn CounterPicker(
+    info: OnceResource<Result<DeploymentInfo, ServerFnError<String>>>,
+    counter: RwSignal<Option<String>>,
+) -> impl IntoView {
+    view! {
+        <Suspense>
+            {move || {
+                info.get()
+                    .and_then(Result::ok)
+                    .filter(|info| !info.counters.is_empty())
+                    .map(|DeploymentInfo { counters, .. }| {
+                        view! {
+                            <label class="env-switcher counter-picker">
+                                "Counter "
+                                <select
+                                    prop:value=move || counter.get().unwrap_or_default()
+                                    on:change=move |ev| {
+                                        let name = event_target_value(&ev);
+                                        counter.set((!name.is_empty()).then_some(name));
+                                    }
+                                >
+                                    <option value="">"default"</option>
+                                    {counters
+                                        .into_iter()
+                                        .map(|name| {
+                                            let label = name.clone();
+                                            view! { <option value=name>{label}</option> }
+                                        })
+                                        .collect_view()}
+                                </select>
+                            </label>
+                        }
+                    })
+            }}
+        </Suspense>
+    }
+}
+
+/// Repeats the last action through the button group that sent it, once it has
+/// failed. Bound to `r` as well.
+#[component]
+fn RetryLast(last_action: LastActionState) -> impl IntoView {
+    let shortcut = window_event_listener(leptos::ev::keydown, move |ev| {
+        if shortcut_key(&ev).as_deref() == Some("r") && last_action.can_retry() {
+            ev.prevent_default();
+            last_action.retry();
+        }
+    });
+    on_cleanup(move || shortcut.remove());
+
+    view! {
+        <button
+            class="counter-btn retry-btn"
+            on:click=move |_| last_action.retry()
+            disabled=move || !last_action.can_retry()
+            aria-label="Retry the last failed counter action"
+            aria-keyshortcuts="r"
+        >
+            "Retry last" <kbd>"r"</kbd>
+        </button>
+    }
+}
+
 #[component]
 fn HomePage() -> impl IntoView {
     let (text, set_text) = signal("Click Get to retrieve value".to_string());
+    let (error_kind, set_error_kind) = signal(None::<CallerErrorKind>);
+    let history = RwSignal::new(CounterHistory::default());
+    let info = OnceResource::new(get_deployment_info());
+    // Named counter the server-side buttons operate on, `None` for the default one
+    let counter = RwSignal::new(None::<String>);
+    // Whether the latest server-side call went out without an identity
+    let anonymous = RwSignal::new(None::<bool>);
+    let last_action = LastActionState::new();
+
+    // Built lazily, inside the error boundary, so the buttons can find its context
+    #[cfg(feature = "client-calls")]
+    let client_buttons = move || {
+        view! {
+            <h4>These Buttons call the same canister directly from the browser</h4>
+            <EnvironmentSwitcher/>
+            <ConnectionStatus/>
+            <AuthControls set_text/>
+            <ClientCallerButtons set_text set_error_kind history last_action/>
+        }
+    };
+    #[cfg(not(feature = "client-calls"))]
+    let client_buttons = || ();
 
     view! {
         <h1>"Welcome to Saurabh's Onboarding Project"</h1>
+        <DeploymentBanner info/>
+        <AnonymousWarning info anonymous/>
         <div class="button-container">
-            <h4>These Buttons call the same canister from our axum webserver</h4>
-            <ServerCallerButtons set_text/>
-            <h4>These Buttons call the same canister directly from the browser</h4>
-            <ClientCallerButtons set_text/>
+            <CounterErrorBoundary>
+                <h4>These Buttons call the same canister from our axum webserver</h4>
+                <CounterPicker info counter/>
+                <ServerCallerButtons set_text set_error_kind history counter anonymous last_action/>
+                {client_buttons()}
+            </CounterErrorBoundary>
+            <RetryLast last_action/>
         </div>
-        <p class="counter-result">
+        <p class=move || match error_kind.get() {
+            None => "counter-result",
+            Some(CallerErrorKind::Transport | CallerErrorKind::Timeout) => "counter-result result-warning",
+            Some(CallerErrorKind::Reject | CallerErrorKind::Decode | CallerErrorKind::Unavailable) => "counter-result result-error",
+        } role="status" aria-live="polite" aria-atomic="true" aria-label="Counter result">
             {move || text.get()}
         </p>
+        <LiveCounterValue/>
+        <ExportButtons/>
+        <HistoryLog history set_text/>
+    }
+}
+
+/// Save `contents` as a file through a throwaway download link
+fn download(filename: &str, mime_type: &str, contents: &str) {
+    let href = format!(
+        "data:{};charset=utf-8,{}",
+        mime_type,
+        String::from(web_sys::js_sys::encode_uri_component(contents))
+    );
+    let Ok(link) = document().create_element("a") else {
+        return;
+    };
+    _ = link.set_attribute("href", &href);
+    _ = link.set_attribute("download", filename);
+    link.unchecked_into::<HtmlElement>().click();
+}
+
+/// Download the counter's current state, as the server reads it, for a report
+#[component]
+fn ExportButtons() -> impl IntoView {
+    let export = ServerAction::<ExportCounterState>::new();
+    // The format asked for last, to name the file once the server answers
+    let requested = StoredValue::new(ExportFormat::Json);
+    let (error, set_error) = signal(None::<String>);
+
+    Effect::new(move || match export.value().get() {
+        Some(Ok(contents)) => {
+            set_error(None);
+            let format = requested.get_value();
+            download(
+                &format!("counter-state.{}", format.extension()),
+                format.mime_type(),
+                &contents,
+            );
+        }
+        Some(Err(e)) => set_error(Some(format!("Export failed: {}", e))),
+        None => {}
+    });
+    let dispatch = move |format: ExportFormat| {
+        requested.set_value(format);
+        export.dispatch(ExportCounterState { format });
+    };
+
+    view! {
+        <div class="export-buttons">
+            <button
+                class="counter-btn"
+                on:click=move |_| dispatch(ExportFormat::Json)
+                disabled=move || export.pending().get()
+            >
+                "Download JSON"
+            </button>
+            <button
+                class="counter-btn"
+                on:click=move |_| dispatch(ExportFormat::Csv)
+                disabled=move || export.pending().get()
+            >
+                "Download CSV"
+            </button>
+            {move || error.get().map(|error| view! { <p class="result-error">{error}</p> })}
+        </div>
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn server(counter: Option<&str>) -> HistoryTarget {
+        HistoryTarget::Server {
+            counter: counter.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn at_zero_only_looks_at_the_same_target() {
+        let mut history = CounterHistory::default();
+        history.record(server(None), CallerAction::Decrement, CounterValue(0));
+        history.record(server(Some("blue")), CallerAction::Increment, CounterValue(4));
+        assert!(history.at_zero(&server(None)));
+        assert!(!history.at_zero(&server(Some("blue"))));
+        assert!(!history.at_zero(&server(Some("green"))));
+    }
+
+    #[test]
+    fn undo_goes_back_to_the_entry_target() {
+        let mut history = CounterHistory::default();
+        history.record(server(Some("blue")), CallerAction::Increment, CounterValue(4));
+        #[cfg(feature = "client-calls")]
+        history.record(
+            HistoryTarget::Client { env: "local" },
+            CallerAction::Get,
+            CounterValue(9),
+        );
+        let undo = history.last_undo().unwrap();
+        assert_eq!(undo.target, server(Some("blue")));
+        assert!(matches!(undo.inverse, CallerAction::Decrement));
+        assert_eq!(undo.expected, CounterValue(4));
+    }
+
+    #[test]
+    fn undoing_a_set_restores_the_value_seen_on_the_same_target() {
+        let mut history = CounterHistory::default();
+        history.record(server(None), CallerAction::Get, CounterValue(7));
+        history.record(server(Some("blue")), CallerAction::Get, CounterValue(2));
+        history.record(server(None), CallerAction::Set(50), CounterValue(50));
+        let undo = history.last_undo().unwrap();
+        assert!(matches!(undo.inverse, CallerAction::Set(7)));
+
+        let mut history = CounterHistory::default();
+        history.record(server(Some("blue")), CallerAction::Get, CounterValue(2));
+        history.record(server(None), CallerAction::Set(50), CounterValue(50));
+        assert!(history.last_undo().is_err());
+    }
+
+    #[cfg(feature = "ssr")]
+    #[tokio::test]
+    async fn result_and_buttons_render_their_aria_attributes() {
+        // Rendered the way leptos_axum does, which resources and the window
+        // listeners rely on to know they're on the server
+        _ = any_spawner::Executor::init_tokio();
+        let owner = Owner::new_root(Some(std::sync::Arc::new(
+            hydration_context::SsrSharedContext::new(),
+        )));
+        owner.set();
+        #[cfg(feature = "client-calls")]
+        BrowserClient::spawn().provide();
+        let html = view! { <HomePage/> }.to_html();
+
+        assert!(
+            html.contains(
+                r#"role="status" aria-live="polite" aria-atomic="true" aria-label="Counter result""#
+            ),
+            "{}",
+            html
+        );
+        for label in [
+            "Get counter via server",
+            "Increment counter via server",
+            "Decrement counter via server",
+        ] {
+            assert!(
+                html.contains(&format!(r#"aria-label="{}""#, label)),
+                "{}",
+                html
+            );
+        }
+        assert!(html.contains(r#"aria-busy="false""#), "{}", html);
     }
 }
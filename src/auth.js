@@ -0,0 +1,78 @@
+// Internet Identity login for the browser-side ICClient.
+//
+// AuthClient keeps its session key as a non-extractable ECDSA key in
+// IndexedDB. Rust can't sign with that key, so on every page load the
+// session delegates on to an Ed25519 key that only ever lives in memory, and
+// Rust signs with that one. The agent-js packages are bundled by js/ and
+// served from this site, see js/package.json.
+import { AuthClient, DelegationChain, Ed25519KeyIdentity } from "/vendor/dfinity.js";
+
+// Where earlier versions stored the Ed25519 key in plaintext
+const LEGACY_SESSION_KEY_STORAGE = "onboarding-counter.session-key";
+
+let authClient;
+let pageKey;
+let pageChain;
+
+async function getAuthClient() {
+    if (!authClient) {
+        localStorage.removeItem(LEGACY_SESSION_KEY_STORAGE);
+        authClient = await AuthClient.create();
+    }
+    return authClient;
+}
+
+// Extend the II delegation to this page's in-memory key, for no longer than
+// the II delegation itself lasts
+async function exportIdentity(identity) {
+    const previous = identity.getDelegation();
+    if (!pageKey || !pageChain) {
+        pageKey = Ed25519KeyIdentity.generate();
+        const expiration = previous.delegations
+            .map((signed) => signed.delegation.expiration)
+            .reduce((earliest, expiration) => (expiration < earliest ? expiration : earliest));
+        pageChain = await DelegationChain.create(
+            identity,
+            pageKey.getPublicKey(),
+            new Date(Number(expiration / BigInt(1_000_000))),
+            { previous },
+        );
+    }
+    return JSON.stringify({
+        secretKey: pageKey.toJSON()[1],
+        delegationChain: pageChain.toJSON(),
+    });
+}
+
+export async function login(identityProvider) {
+    const client = await getAuthClient();
+    await new Promise((resolve, reject) => {
+        client.login({
+            identityProvider,
+            onSuccess: resolve,
+            onError: (error) => reject(new Error(error ?? "Login cancelled")),
+        });
+    });
+    pageKey = undefined;
+    pageChain = undefined;
+    return exportIdentity(client.getIdentity());
+}
+
+// Returns the exported identity if a previous login is still valid, otherwise null
+export async function restoreSession() {
+    const client = await getAuthClient();
+    if (!(await client.isAuthenticated())) {
+        return null;
+    }
+    return exportIdentity(client.getIdentity());
+}
+
+// Drops the in-memory key, and AuthClient deletes its stored key and delegation
+export async function logout() {
+    const client = await getAuthClient();
+    pageKey = undefined;
+    pageChain = undefined;
+    await client.logout();
+    // The next login starts from a fresh AuthClient and key
+    authClient = undefined;
+}
@@ -0,0 +1,172 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use ic_agent::Identity;
+
+/// Internet Identity provider used by the browser login flow
+pub const IDENTITY_PROVIDER: &str = "https://identity.ic0.app";
+
+#[cfg(feature = "hydrate")]
+mod js {
+    use wasm_bindgen::prelude::*;
+
+    #[wasm_bindgen(module = "/src/auth.js")]
+    extern "C" {
+        #[wasm_bindgen(catch)]
+        pub async fn login(identity_provider: &str) -> Result<JsValue, JsValue>;
+
+        #[wasm_bindgen(catch, js_name = restoreSession)]
+        pub async fn restore_session() -> Result<JsValue, JsValue>;
+
+        #[wasm_bindgen(catch)]
+        pub async fn logout() -> Result<JsValue, JsValue>;
+    }
+}
+
+#[cfg(feature = "hydrate")]
+mod exported {
+    use anyhow::{anyhow, Result};
+    use ic_agent::export::Principal;
+    use ic_agent::identity::{BasicIdentity, DelegatedIdentity, Delegation, SignedDelegation};
+    use ic_agent::Identity;
+    use serde::Deserialize;
+
+    /// The page's in-memory session key and the delegation chain ending at it,
+    /// handed over by `auth.js`
+    #[derive(Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    pub struct ExportedIdentity {
+        secret_key: String,
+        delegation_chain: JsonDelegationChain,
+    }
+
+    /// `DelegationChain.toJSON()` from agent-js; all bytes are hex encoded
+    #[derive(Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    struct JsonDelegationChain {
+        public_key: String,
+        delegations: Vec<JsonSignedDelegation>,
+    }
+
+    #[derive(Deserialize)]
+    struct JsonSignedDelegation {
+        delegation: JsonDelegation,
+        signature: String,
+    }
+
+    #[derive(Deserialize)]
+    struct JsonDelegation {
+        pubkey: String,
+        /// Nanosecond timestamp as a hex string
+        expiration: String,
+        targets: Option<Vec<String>>,
+    }
+
+    impl ExportedIdentity {
+        pub fn into_identity(self) -> Result<DelegatedIdentity> {
+            let secret_key =
+                hex::decode(&self.secret_key).map_err(|e| anyhow!("Invalid session key: {}", e))?;
+            // Older agent-js versions export the 64 byte seed || public key form
+            let seed: [u8; 32] = secret_key
+                .get(..32)
+                .and_then(|seed| seed.try_into().ok())
+                .ok_or_else(|| anyhow!("Session key is too short"))?;
+            let session = BasicIdentity::from_raw_key(&seed);
+
+            let chain = self
+                .delegation_chain
+                .delegations
+                .into_iter()
+                .map(|signed| {
+                    let targets = signed
+                        .delegation
+                        .targets
+                        .map(|targets| {
+                            targets
+                                .iter()
+                                .map(|target| Ok(Principal::from_slice(&hex::decode(target)?)))
+                                .collect::<Result<Vec<_>>>()
+                        })
+                        .transpose()?;
+                    Ok(SignedDelegation {
+                        delegation: Delegation {
+                            pubkey: hex::decode(&signed.delegation.pubkey)?,
+                            expiration: u64::from_str_radix(&signed.delegation.expiration, 16)?,
+                            targets,
+                        },
+                        signature: hex::decode(&signed.signature)?,
+                    })
+                })
+                .collect::<Result<Vec<_>>>()
+                .map_err(|e| anyhow!("Invalid delegation chain: {}", e))?;
+
+            let from_key = hex::decode(&self.delegation_chain.public_key)
+                .map_err(|e| anyhow!("Invalid delegation public key: {}", e))?;
+            // The chain starts with Internet Identity's canister signature, which
+            // only the replica can check, so just make sure it ends at our key
+            let session_key = session.public_key();
+            if chain.last().map(|signed| &signed.delegation.pubkey) != session_key.as_ref() {
+                return Err(anyhow!("Delegation chain does not end at the session key"));
+            }
+            Ok(DelegatedIdentity::new_unchecked(
+                from_key,
+                Box::new(session),
+                chain,
+            ))
+        }
+    }
+}
+
+#[cfg(feature = "hydrate")]
+fn parse_exported(exported: wasm_bindgen::JsValue) -> Result<Option<Arc<dyn Identity>>> {
+    use anyhow::anyhow;
+
+    let Some(json) = exported.as_string() else {
+        return Ok(None);
+    };
+    let exported: exported::ExportedIdentity = leptos::serde_json::from_str(&json)
+        .map_err(|e| anyhow!("Invalid identity from auth client: {}", e))?;
+    Ok(Some(Arc::new(exported.into_identity()?)))
+}
+
+#[cfg(feature = "hydrate")]
+fn js_error(e: wasm_bindgen::JsValue) -> anyhow::Error {
+    anyhow::anyhow!("{}", e.as_string().unwrap_or_else(|| format!("{:?}", e)))
+}
+
+/// Open the Internet Identity popup and return the delegated identity on success
+#[cfg(feature = "hydrate")]
+pub async fn login() -> Result<Arc<dyn Identity>> {
+    let exported = js::login(IDENTITY_PROVIDER).await.map_err(js_error)?;
+    parse_exported(exported)?.ok_or_else(|| anyhow::anyhow!("Login returned no identity"))
+}
+
+/// The identity from a previous login, if its delegation hasn't expired
+#[cfg(feature = "hydrate")]
+pub async fn restore_session() -> Result<Option<Arc<dyn Identity>>> {
+    let exported = js::restore_session().await.map_err(js_error)?;
+    parse_exported(exported)
+}
+
+#[cfg(feature = "hydrate")]
+pub async fn logout() -> Result<()> {
+    js::logout().await.map_err(js_error)?;
+    Ok(())
+}
+
+#[cfg(not(feature = "hydrate"))]
+pub async fn login() -> Result<Arc<dyn Identity>> {
+    Err(anyhow::anyhow!(
+        "Internet Identity login is only available in the browser"
+    ))
+}
+
+#[cfg(not(feature = "hydrate"))]
+pub async fn restore_session() -> Result<Option<Arc<dyn Identity>>> {
+    Ok(None)
+}
+
+#[cfg(not(feature = "hydrate"))]
+pub async fn logout() -> Result<()> {
+    Ok(())
+}
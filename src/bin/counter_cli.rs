@@ -0,0 +1,116 @@
+use std::process::ExitCode;
+use std::time::Duration;
+
+use candid::Nat;
+use clap::{Parser, Subcommand};
+use futures::StreamExt;
+use onboarding_counter::ic_agent::{
+    create_client_from_config, dry_run_from_env, load_env_config, CounterValue, ICClient, ICError,
+};
+
+/// Drive the counter canister from scripts.
+///
+/// Configuration is read from DEPLOYMENT_ENV, COUNTER_CANISTER_ID and
+/// CALLER_CANISTER_ID. The resulting counter value is printed to stdout.
+#[derive(Parser)]
+#[command(name = "counter-cli", version)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Print the current counter value
+    Get,
+    /// Increment the counter and print the new value
+    Increment,
+    /// Decrement the counter and print the new value
+    Decrement,
+    /// Set the counter to a value and print it
+    Set { value: u64 },
+    /// Print the counter value whenever it changes, until interrupted
+    Watch {
+        /// Seconds between polls
+        #[arg(long, default_value_t = 5)]
+        interval_secs: u64,
+    },
+    /// Fire many increments at once and report throughput, latency and errors.
+    /// Every increment really changes the counter unless DRY_RUN is set.
+    /// Exits non-zero if any increment failed.
+    Stress {
+        /// Increments to make in total
+        #[arg(long, default_value_t = 100)]
+        total: usize,
+        /// Most increments in flight at once
+        #[arg(long, default_value_t = 10)]
+        concurrency: usize,
+    },
+}
+
+impl Command {
+    /// What to print on success: the resulting value, or a report for `stress`
+    async fn run(self, client: &ICClient) -> anyhow::Result<String> {
+        let value = match self {
+            Command::Get => client.caller_get().await,
+            Command::Increment => client.caller_increment().await,
+            Command::Decrement => client.caller_decrement().await,
+            Command::Set { value } => client.caller_set(Nat::from(value)).await,
+            Command::Watch { interval_secs } => {
+                watch(client, Duration::from_secs(interval_secs)).await
+            }
+            Command::Stress { total, concurrency } => {
+                let report = client.stress(total, concurrency).await;
+                if report.failed() > 0 {
+                    println!("{}", report);
+                    anyhow::bail!("{} of {} increments failed", report.failed(), report.total);
+                }
+                return Ok(report.to_string());
+            }
+        }?;
+        Ok(value.to_string())
+    }
+}
+
+/// Print each new value as it is observed. Read errors are reported and polling
+/// continues, so only the end of the stream returns.
+async fn watch(client: &ICClient, interval: Duration) -> Result<CounterValue, ICError> {
+    let mut last = None;
+    let mut values = std::pin::pin!(client.watch(interval));
+    while let Some(value) = values.next().await {
+        match value {
+            Ok(value) if last != Some(value) => {
+                println!("{}", value);
+                last = Some(value);
+            }
+            Ok(_) => {}
+            Err(e) => eprintln!("Error: {}", e),
+        }
+    }
+    Ok(last.unwrap_or_default())
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let cli = Cli::parse();
+
+    let result: anyhow::Result<String> = async {
+        let config = load_env_config()?;
+        let client = create_client_from_config(&config)
+            .await?
+            .with_dry_run(dry_run_from_env());
+        cli.command.run(&client).await
+    }
+    .await;
+
+    match result {
+        Ok(output) => {
+            println!("{}", output);
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("Error: {:#}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
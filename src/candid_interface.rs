@@ -0,0 +1,561 @@
+use anyhow::{anyhow, Result};
+use candid::types::subtype::{subtype_with_config, Gamma, OptReport};
+use candid::types::{Field, FuncMode, Function, Label, Type, TypeEnv, TypeInner};
+use candid::{CandidType, Nat, Principal};
+use std::collections::HashMap;
+
+use crate::ic_agent::MethodNames;
+
+/// Caller canister methods [`crate::ic_agent::ICClient`] calls, with the argument
+/// types it encodes for each. The idempotent increment is only called, and so
+/// only expected, when `idempotent_increments` is on.
+pub fn expected_caller_methods(
+    names: &MethodNames,
+    idempotent_increments: bool,
+) -> Vec<(&str, Vec<Type>)> {
+    let mut methods = vec![
+        (names.get.as_str(), vec![Principal::ty()]),
+        (names.increment.as_str(), vec![Principal::ty()]),
+        (names.decrement.as_str(), vec![Principal::ty()]),
+        (names.set.as_str(), vec![Principal::ty(), Nat::ty()]),
+    ];
+    if idempotent_increments {
+        methods.push((
+            names.increment_idempotent.as_str(),
+            vec![Principal::ty(), String::ty()],
+        ));
+    }
+    methods
+}
+
+/// Replies [`crate::ic_agent::CounterReturn::decode`] accepts
+fn counter_return_types() -> [Type; 2] {
+    [Result::<Nat, String>::ty(), Nat::ty()]
+}
+
+/// Check the caller canister's Candid interface at `path` against
+/// [`expected_caller_methods`], so interface drift shows up at startup instead
+/// of on the first click
+pub fn check_caller_did(
+    path: &str,
+    names: &MethodNames,
+    idempotent_increments: bool,
+) -> Result<()> {
+    let did = std::fs::read_to_string(path)
+        .map_err(|e| anyhow!("Failed to read Candid interface {}: {}", path, e))?;
+    check_caller_interface(&did, names, idempotent_increments)
+        .map_err(|e| anyhow!("Caller canister interface {} doesn't match: {}", path, e))
+}
+
+/// [`check_caller_did`] on the text of a `.did` file. Each method has to accept
+/// the arguments the client encodes and reply with something
+/// [`crate::ic_agent::CounterReturn`] decodes, by Candid's subtyping rules.
+pub fn check_caller_interface(
+    did: &str,
+    names: &MethodNames,
+    idempotent_increments: bool,
+) -> Result<()> {
+    let (env, methods) = service_methods(did)?;
+    let fits = |sent: &[Type], declared: &[Type]| {
+        sent.len() <= declared.len()
+            && subtype_with_config(
+                OptReport::Silence,
+                &mut Gamma::new(),
+                &env,
+                &tuple(sent),
+                &tuple(declared),
+            )
+            .is_ok()
+    };
+    let problems: Vec<String> = expected_caller_methods(names, idempotent_increments)
+        .into_iter()
+        .filter_map(|(method, args)| {
+            let Some(func) = methods.get(method) else {
+                return Some(format!("{} is missing", method));
+            };
+            if !fits(&args, &func.args) {
+                return Some(format!(
+                    "{} takes {} but is called with {}",
+                    method,
+                    signature(&func.args),
+                    signature(&args)
+                ));
+            }
+            if !counter_return_types()
+                .iter()
+                .any(|ret| fits(&func.rets, std::slice::from_ref(ret)))
+            {
+                return Some(format!(
+                    "{} returns {} but {} or {} is expected",
+                    method,
+                    signature(&func.rets),
+                    signature(&counter_return_types()[..1]),
+                    signature(&counter_return_types()[1..])
+                ));
+            }
+            None
+        })
+        .collect();
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        Err(anyhow!(problems.join("; ")))
+    }
+}
+
+/// An argument or result sequence as the tuple record Candid compares it as
+fn tuple(types: &[Type]) -> Type {
+    TypeInner::Record(
+        types
+            .iter()
+            .zip(0..)
+            .map(|(ty, i)| Field {
+                id: Label::Id(i).into(),
+                ty: ty.clone(),
+            })
+            .collect(),
+    )
+    .into()
+}
+
+fn signature(types: &[Type]) -> String {
+    let types: Vec<String> = types.iter().map(Type::to_string).collect();
+    format!("({})", types.join(", "))
+}
+
+/// The type definitions in `did` and the signature of every method its service
+/// declares. Type names are left as [`TypeInner::Var`]s to look up in the
+/// returned [`TypeEnv`].
+pub fn service_methods(did: &str) -> Result<(TypeEnv, HashMap<String, Function>)> {
+    let mut parser = Parser::new(did)?;
+    let mut env = TypeEnv::new();
+    let mut service = None;
+    while !parser.at_end() {
+        match parser.ident()?.as_str() {
+            "type" => {
+                let name = parser.ident()?;
+                parser.expect("=")?;
+                let ty = parser.data_type()?;
+                env.0.insert(name, ty);
+            }
+            "service" => {
+                if !parser.eat(":") {
+                    parser.ident()?;
+                    parser.expect(":")?;
+                }
+                if parser.peek_is("(") {
+                    // Init arguments, which don't matter to callers
+                    parser.tuple()?;
+                    parser.expect("->")?;
+                }
+                service = Some(parser.service_type()?);
+            }
+            "import" => return Err(anyhow!("Imports aren't supported, inline the types")),
+            other => {
+                return Err(anyhow!(
+                    "Expected a type or service definition at '{}'",
+                    other
+                ))
+            }
+        }
+        parser.eat(";");
+    }
+    let service = service.ok_or_else(|| anyhow!("No service definition found"))?;
+
+    let mut methods = HashMap::new();
+    for (name, ty) in env.as_service(&service)? {
+        methods.insert(name.clone(), env.as_func(ty)?.clone());
+    }
+    Ok((env, methods))
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Ident(String),
+    Text(String),
+    Number(u32),
+    Symbol(&'static str),
+}
+
+/// Recursive descent over the parts of the Candid grammar a service needs
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn new(did: &str) -> Result<Self> {
+        Ok(Self {
+            tokens: tokenize(did)?,
+            position: 0,
+        })
+    }
+
+    fn at_end(&self) -> bool {
+        self.position >= self.tokens.len()
+    }
+
+    fn next(&mut self) -> Result<Token> {
+        let token = self
+            .tokens
+            .get(self.position)
+            .cloned()
+            .ok_or_else(|| anyhow!("Unexpected end of Candid interface"))?;
+        self.position += 1;
+        Ok(token)
+    }
+
+    fn peek_is(&self, symbol: &str) -> bool {
+        matches!(self.tokens.get(self.position), Some(Token::Symbol(s)) if *s == symbol)
+    }
+
+    /// Whether the token after the next one is `symbol`, e.g. the `:` after a field name
+    fn second_is(&self, symbol: &str) -> bool {
+        matches!(self.tokens.get(self.position + 1), Some(Token::Symbol(s)) if *s == symbol)
+    }
+
+    fn eat(&mut self, symbol: &str) -> bool {
+        let found = self.peek_is(symbol);
+        if found {
+            self.position += 1;
+        }
+        found
+    }
+
+    fn expect(&mut self, symbol: &str) -> Result<()> {
+        match self.next()? {
+            Token::Symbol(s) if s == symbol => Ok(()),
+            other => Err(anyhow!("Expected '{}' but found {:?}", symbol, other)),
+        }
+    }
+
+    fn ident(&mut self) -> Result<String> {
+        match self.next()? {
+            Token::Ident(ident) => Ok(ident),
+            other => Err(anyhow!("Expected a name but found {:?}", other)),
+        }
+    }
+
+    /// A method or field name, which may be quoted
+    fn name(&mut self) -> Result<String> {
+        match self.next()? {
+            Token::Ident(name) | Token::Text(name) => Ok(name),
+            other => Err(anyhow!("Expected a name but found {:?}", other)),
+        }
+    }
+
+    fn data_type(&mut self) -> Result<Type> {
+        let ident = self.ident()?;
+        let inner = match ident.as_str() {
+            "null" => TypeInner::Null,
+            "bool" => TypeInner::Bool,
+            "nat" => TypeInner::Nat,
+            "int" => TypeInner::Int,
+            "nat8" => TypeInner::Nat8,
+            "nat16" => TypeInner::Nat16,
+            "nat32" => TypeInner::Nat32,
+            "nat64" => TypeInner::Nat64,
+            "int8" => TypeInner::Int8,
+            "int16" => TypeInner::Int16,
+            "int32" => TypeInner::Int32,
+            "int64" => TypeInner::Int64,
+            "float32" => TypeInner::Float32,
+            "float64" => TypeInner::Float64,
+            "text" => TypeInner::Text,
+            "reserved" => TypeInner::Reserved,
+            "empty" => TypeInner::Empty,
+            "principal" => TypeInner::Principal,
+            "blob" => TypeInner::Vec(TypeInner::Nat8.into()),
+            "opt" => TypeInner::Opt(self.data_type()?),
+            "vec" => TypeInner::Vec(self.data_type()?),
+            "record" => TypeInner::Record(self.fields(false)?),
+            "variant" => TypeInner::Variant(self.fields(true)?),
+            "func" => TypeInner::Func(self.func_type()?),
+            "service" => return self.service_type(),
+            _ => TypeInner::Var(ident),
+        };
+        Ok(inner.into())
+    }
+
+    /// `{ a : T; 1 : T; T }`. A record field without a name is numbered by its
+    /// position, and a variant field without a type is `null`.
+    fn fields(&mut self, variant: bool) -> Result<Vec<Field>> {
+        self.expect("{")?;
+        let mut fields = Vec::new();
+        let mut next_unnamed = 0;
+        while !self.eat("}") {
+            let named = self.second_is(":") || variant;
+            let (label, ty) = if named {
+                let label = match self.next()? {
+                    Token::Number(id) => Label::Id(id),
+                    Token::Ident(name) | Token::Text(name) => Label::Named(name),
+                    other => return Err(anyhow!("Expected a field name but found {:?}", other)),
+                };
+                let ty = if self.eat(":") {
+                    self.data_type()?
+                } else {
+                    TypeInner::Null.into()
+                };
+                (label, ty)
+            } else {
+                let label = Label::Unnamed(next_unnamed);
+                (label, self.data_type()?)
+            };
+            next_unnamed = label.get_id() + 1;
+            fields.push(Field {
+                id: label.into(),
+                ty,
+            });
+            if !self.eat(";") {
+                self.expect("}")?;
+                break;
+            }
+        }
+        fields.sort_by_key(|field| field.id.get_id());
+        Ok(fields)
+    }
+
+    /// `(a : T, T)`, dropping argument names
+    fn tuple(&mut self) -> Result<Vec<Type>> {
+        self.expect("(")?;
+        let mut types = Vec::new();
+        while !self.eat(")") {
+            if self.second_is(":") {
+                self.name()?;
+                self.expect(":")?;
+            }
+            types.push(self.data_type()?);
+            if !self.eat(",") {
+                self.expect(")")?;
+                break;
+            }
+        }
+        Ok(types)
+    }
+
+    fn func_type(&mut self) -> Result<Function> {
+        let args = self.tuple()?;
+        self.expect("->")?;
+        let rets = self.tuple()?;
+        let mut modes = Vec::new();
+        loop {
+            let mode = match self.tokens.get(self.position) {
+                Some(Token::Ident(mode)) if mode == "query" => FuncMode::Query,
+                Some(Token::Ident(mode)) if mode == "composite_query" => FuncMode::CompositeQuery,
+                Some(Token::Ident(mode)) if mode == "oneway" => FuncMode::Oneway,
+                _ => break,
+            };
+            modes.push(mode);
+            self.position += 1;
+        }
+        Ok(Function { modes, args, rets })
+    }
+
+    /// `{ method : (..) -> (..); other : FuncAlias }` or the name of a service type
+    fn service_type(&mut self) -> Result<Type> {
+        if !self.peek_is("{") {
+            return Ok(TypeInner::Var(self.ident()?).into());
+        }
+        self.expect("{")?;
+        let mut methods = Vec::new();
+        while !self.eat("}") {
+            let name = self.name()?;
+            self.expect(":")?;
+            let ty = if self.peek_is("(") {
+                TypeInner::Func(self.func_type()?).into()
+            } else {
+                TypeInner::Var(self.ident()?).into()
+            };
+            methods.push((name, ty));
+            if !self.eat(";") {
+                self.expect("}")?;
+                break;
+            }
+        }
+        methods.sort_by(|a, b| a.0.cmp(&b.0));
+        Ok(TypeInner::Service(methods).into())
+    }
+}
+
+/// Split `did` into tokens, dropping whitespace and `//` and `/* */` comments
+fn tokenize(did: &str) -> Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut chars = did.char_indices().peekable();
+    while let Some((start, c)) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {}
+            '/' if did[start..].starts_with("//") => {
+                while chars.next_if(|(_, c)| *c != '\n').is_some() {}
+            }
+            '/' if did[start..].starts_with("/*") => {
+                let end = did[start..]
+                    .find("*/")
+                    .ok_or_else(|| anyhow!("Unclosed comment"))?;
+                while chars.next_if(|(i, _)| *i < start + end + 2).is_some() {}
+            }
+            '-' if did[start..].starts_with("->") => {
+                chars.next();
+                tokens.push(Token::Symbol("->"));
+            }
+            '(' => tokens.push(Token::Symbol("(")),
+            ')' => tokens.push(Token::Symbol(")")),
+            '{' => tokens.push(Token::Symbol("{")),
+            '}' => tokens.push(Token::Symbol("}")),
+            ':' => tokens.push(Token::Symbol(":")),
+            ';' => tokens.push(Token::Symbol(";")),
+            ',' => tokens.push(Token::Symbol(",")),
+            '=' => tokens.push(Token::Symbol("=")),
+            '"' => {
+                let mut text = String::new();
+                loop {
+                    match chars.next() {
+                        Some((_, '"')) => break,
+                        Some((_, '\\')) => text.extend(chars.next().map(|(_, c)| c)),
+                        Some((_, c)) => text.push(c),
+                        None => return Err(anyhow!("Unclosed string")),
+                    }
+                }
+                tokens.push(Token::Text(text));
+            }
+            c if c.is_ascii_digit() => {
+                let mut end = start + 1;
+                while let Some((i, _)) = chars.next_if(|(_, c)| c.is_ascii_digit() || *c == '_') {
+                    end = i + 1;
+                }
+                let number = did[start..end].replace('_', "");
+                tokens.push(Token::Number(
+                    number
+                        .parse()
+                        .map_err(|e| anyhow!("Invalid field id {}: {}", number, e))?,
+                ));
+            }
+            c if c.is_alphabetic() || c == '_' => {
+                let mut end = start + c.len_utf8();
+                while let Some((i, c)) = chars.next_if(|(_, c)| c.is_alphanumeric() || *c == '_') {
+                    end = i + c.len_utf8();
+                }
+                tokens.push(Token::Ident(did[start..end].to_string()));
+            }
+            other => return Err(anyhow!("Unexpected '{}' in Candid interface", other)),
+        }
+    }
+    Ok(tokens)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The caller canister's interface, spelled with aliases, argument names and
+    /// comments the way `dfx` writes it
+    const CALLER_DID: &str = r#"
+        // Generated by dfx
+        type CanisterId = principal;
+        type Result = variant { Ok : nat; Err : text };
+        /* Every method forwards to the counter canister */
+        type Forward = func (counter : CanisterId) -> (Result);
+        service : (opt record { owner : principal }) -> {
+          call_get : Forward;
+          call_increment : (CanisterId) -> (Result);
+          "call_decrement" : (counter : principal) -> (variant { Err : text; Ok : nat });
+          call_set : (principal, value : nat) -> (nat);
+          call_increment_idempotent : (principal, text) -> (Result);
+          greet : (text) -> (text) query;
+        }
+    "#;
+
+    fn check(did: &str) -> Result<()> {
+        check_caller_interface(did, &MethodNames::default(), false)
+    }
+
+    #[test]
+    fn matching_interface_passes() {
+        check(CALLER_DID).unwrap();
+        check_caller_interface(CALLER_DID, &MethodNames::default(), true).unwrap();
+    }
+
+    #[test]
+    fn reads_every_method_signature() {
+        let (env, methods) = service_methods(CALLER_DID).unwrap();
+        assert_eq!(methods.len(), 6);
+        assert!(methods["greet"].is_query());
+        assert_eq!(methods["call_set"].args.len(), 2);
+        assert_eq!(
+            env.trace_type(&methods["call_get"].rets[0]).unwrap(),
+            TypeInner::Variant(vec![
+                Field {
+                    id: Label::Named("Ok".to_string()).into(),
+                    ty: TypeInner::Nat.into()
+                },
+                Field {
+                    id: Label::Named("Err".to_string()).into(),
+                    ty: TypeInner::Text.into()
+                },
+            ])
+            .into()
+        );
+    }
+
+    #[test]
+    fn drifted_argument_type_is_reported() {
+        let drifted = CALLER_DID.replace(
+            "call_set : (principal, value : nat)",
+            "call_set : (principal, value : text)",
+        );
+        let error = check(&drifted).unwrap_err().to_string();
+        assert!(
+            error.starts_with("call_set takes (principal, text)"),
+            "{}",
+            error
+        );
+        // A nat still decodes as an int, so widening isn't drift
+        check(&CALLER_DID.replace("value : nat", "value : int")).unwrap();
+    }
+
+    #[test]
+    fn drifted_return_type_is_reported() {
+        let drifted = CALLER_DID.replace("-> (nat)", "-> (nat64)");
+        let error = check(&drifted).unwrap_err().to_string();
+        assert!(error.starts_with("call_set returns (nat64)"), "{}", error);
+    }
+
+    #[test]
+    fn missing_method_is_reported() {
+        let drifted = CALLER_DID.replace("call_increment :", "call_increment_by_one :");
+        assert_eq!(
+            check(&drifted).unwrap_err().to_string(),
+            "call_increment is missing"
+        );
+    }
+
+    #[test]
+    fn idempotent_increment_is_only_required_when_enabled() {
+        let without = CALLER_DID.replace("call_increment_idempotent :", "unrelated :");
+        check(&without).unwrap();
+        let error = check_caller_interface(&without, &MethodNames::default(), true)
+            .unwrap_err()
+            .to_string();
+        assert_eq!(error, "call_increment_idempotent is missing");
+    }
+
+    #[test]
+    fn optional_trailing_arguments_still_fit() {
+        let extended = CALLER_DID.replace(
+            "call_increment : (CanisterId)",
+            "call_increment : (CanisterId, opt nat)",
+        );
+        check(&extended).unwrap();
+    }
+
+    #[test]
+    fn renamed_methods_are_checked_under_their_names() {
+        let names = MethodNames {
+            get: "greet".to_string(),
+            ..MethodNames::default()
+        };
+        let error = check_caller_interface(CALLER_DID, &names, false)
+            .unwrap_err()
+            .to_string();
+        assert!(error.starts_with("greet takes (text)"), "{}", error);
+    }
+}
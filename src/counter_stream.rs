@@ -0,0 +1,100 @@
+use std::time::Duration;
+
+#[cfg(feature = "ssr")]
+use {
+    crate::ic_agent::ICClient,
+    axum::response::sse::{Event, KeepAlive, Sse},
+    futures::{future, Stream, StreamExt},
+    leptos::logging::warn,
+    std::{convert::Infallible, sync::Arc},
+    tokio::sync::watch,
+    tokio_stream::wrappers::WatchStream,
+};
+
+/// Route serving live counter values as Server-Sent Events
+pub const COUNTER_STREAM_PATH: &str = "/counter/stream";
+
+/// How often the shared poller queries the caller canister
+pub const DEFAULT_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How long the browser waits before reopening a stream the server closed
+pub const RECONNECT_DELAY: Duration = Duration::from_secs(3);
+
+/// Latest counter value shared between the poller and every connected browser
+#[cfg(feature = "ssr")]
+#[derive(Clone)]
+pub struct CounterUpdates {
+    sender: Arc<watch::Sender<Option<String>>>,
+    closed: Arc<watch::Sender<bool>>,
+}
+
+#[cfg(feature = "ssr")]
+impl CounterUpdates {
+    /// Spawn a single background poller for `client`. Every SSE connection
+    /// subscribes to its result, so the canister is queried once per tick no
+    /// matter how many browsers are watching.
+    pub fn spawn(client: ICClient, interval: Duration) -> Self {
+        let (sender, _) = watch::channel(None);
+        let sender = Arc::new(sender);
+        let closed = Arc::new(watch::channel(false).0);
+
+        let poller = sender.clone();
+        let poller_closed = closed.clone();
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            loop {
+                ticker.tick().await;
+                if *poller_closed.borrow() {
+                    break;
+                }
+                // Nobody is listening, don't spend canister calls
+                if poller.receiver_count() == 0 {
+                    continue;
+                }
+                match client.caller_get().await {
+                    Ok(value) => {
+                        let value = value.to_string();
+                        // Only wake subscribers when the value actually changed
+                        poller.send_if_modified(|current| {
+                            if current.as_deref() == Some(value.as_str()) {
+                                false
+                            } else {
+                                *current = Some(value);
+                                true
+                            }
+                        });
+                    }
+                    Err(e) => warn!("Counter stream poll failed: {}", e),
+                }
+            }
+        });
+
+        Self { sender, closed }
+    }
+
+    /// End every open stream and stop polling. Browsers hold SSE connections open
+    /// indefinitely, so graceful shutdown would otherwise never finish draining.
+    pub fn close(&self) {
+        self.closed.send_replace(true);
+    }
+
+    /// Stream of SSE events, starting with the last known value if there is one
+    pub fn subscribe(&self) -> impl Stream<Item = Result<Event, Infallible>> {
+        let mut closed = self.closed.subscribe();
+        WatchStream::new(self.sender.subscribe())
+            .filter_map(future::ready)
+            .map(|value| Ok(Event::default().event("value").data(value)))
+            .take_until(async move {
+                _ = closed.wait_for(|closed| *closed).await;
+            })
+    }
+}
+
+/// Axum handler for [`COUNTER_STREAM_PATH`]
+#[cfg(feature = "ssr")]
+pub fn counter_stream(
+    updates: &CounterUpdates,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    Sse::new(updates.subscribe()).keep_alive(KeepAlive::default())
+}
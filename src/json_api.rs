@@ -0,0 +1,138 @@
+#[cfg(feature = "ssr")]
+use {
+    crate::{
+        ic_agent::{CounterValue, ICClient, ICError},
+        server_functions::CounterActionError,
+    },
+    axum::{
+        extract::State,
+        http::StatusCode,
+        response::{IntoResponse, Response},
+        routing::{get, post},
+        Json, Router,
+    },
+    serde::Serialize,
+};
+
+/// Plain JSON route for tools that can't call server functions, `GET` reads the counter
+pub const COUNTER_PATH: &str = "/counter";
+/// `POST` increments the counter
+pub const COUNTER_INCREMENT_PATH: &str = "/counter/increment";
+/// `POST` decrements the counter
+pub const COUNTER_DECREMENT_PATH: &str = "/counter/decrement";
+
+#[cfg(feature = "ssr")]
+#[derive(Serialize)]
+struct ValueBody {
+    /// As a string, since the canister's `Nat` can outgrow JSON numbers in some clients
+    value: String,
+}
+
+/// `GET /counter`, `POST /counter/increment` and `POST /counter/decrement`, all
+/// answering `{"value": "42"}` or a [`CounterActionError`]
+#[cfg(feature = "ssr")]
+pub fn counter_routes<S: Clone + Send + Sync + 'static>(client: ICClient) -> Router<S> {
+    Router::new()
+        .route(
+            COUNTER_PATH,
+            get(|State(client): State<ICClient>| async move { respond(client.caller_get().await) }),
+        )
+        .route(
+            COUNTER_INCREMENT_PATH,
+            post(|State(client): State<ICClient>| async move {
+                respond(client.caller_increment().await)
+            }),
+        )
+        .route(
+            COUNTER_DECREMENT_PATH,
+            post(|State(client): State<ICClient>| async move {
+                respond(client.caller_decrement().await)
+            }),
+        )
+        .with_state(client)
+}
+
+#[cfg(feature = "ssr")]
+fn respond(result: Result<CounterValue, ICError>) -> Response {
+    match result {
+        Ok(value) => Json(ValueBody {
+            value: value.to_string(),
+        })
+        .into_response(),
+        Err(e) => (status_for(&e), Json(CounterActionError::from(&e))).into_response(),
+    }
+}
+
+/// The canister is our upstream, so most failures are a bad gateway
+#[cfg(feature = "ssr")]
+fn status_for(e: &ICError) -> StatusCode {
+    match e {
+        ICError::Underflow => StatusCode::CONFLICT,
+        ICError::Timeout | ICError::StillProcessing { .. } => StatusCode::GATEWAY_TIMEOUT,
+        ICError::UnknownCounter { .. } => StatusCode::NOT_FOUND,
+        ICError::InvalidCanisterId(_) => StatusCode::BAD_REQUEST,
+        ICError::Encode(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        ICError::AgentUnavailable
+        | ICError::Transport(_)
+        | ICError::Reject { .. }
+        | ICError::Decode(_)
+        | ICError::InvalidCertificate
+        | ICError::ReplicaUnreachable { .. }
+        | ICError::CanisterNotFound { .. } => StatusCode::BAD_GATEWAY,
+    }
+}
+
+#[cfg(all(test, feature = "ssr"))]
+mod tests {
+    use super::*;
+    use crate::test_replica::FakeReplica;
+    use axum::body::{to_bytes, Body};
+    use axum::http::Request;
+    use tower::ServiceExt;
+
+    async fn send(app: &Router, request: Request<Body>) -> (StatusCode, serde_json::Value) {
+        let response = app.clone().oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn answers_values_as_strings() {
+        let replica = FakeReplica::start().await;
+        let app: Router = counter_routes(replica.client().build().await.unwrap());
+
+        let increment = Request::post(COUNTER_INCREMENT_PATH)
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(
+            send(&app, increment).await,
+            (StatusCode::OK, serde_json::json!({"value": "1"}))
+        );
+        let get = Request::get(COUNTER_PATH).body(Body::empty()).unwrap();
+        assert_eq!(
+            send(&app, get).await,
+            (StatusCode::OK, serde_json::json!({"value": "1"}))
+        );
+    }
+
+    #[tokio::test]
+    async fn answers_failures_with_their_kind_and_message() {
+        let replica = FakeReplica::start().await;
+        let app: Router = counter_routes(replica.client().build().await.unwrap());
+
+        let decrement = Request::post(COUNTER_DECREMENT_PATH)
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(
+            send(&app, decrement).await,
+            (
+                StatusCode::CONFLICT,
+                serde_json::json!({"error": {
+                    "kind": "Reject",
+                    "message": ICError::Underflow.to_string(),
+                }})
+            )
+        );
+    }
+}
@@ -1,6 +1,14 @@
 pub mod app;
+pub mod auth;
+pub mod candid_interface;
+pub mod counter_stream;
 pub mod ic_agent;
+pub mod json_api;
+pub mod rate_limit;
 pub mod server_functions;
+pub mod telemetry;
+#[cfg(all(test, feature = "ssr"))]
+mod test_replica;
 
 
 
@@ -1,27 +1,74 @@
 #[cfg(feature = "ssr")]
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    use axum::Router;
+    use axum::{routing::get, Router};
 
     use leptos::logging::log;
     use leptos::prelude::provide_context;
     use leptos::prelude::*;
     use leptos_axum::{generate_route_list, LeptosRoutes};
     use onboarding_counter::app::*;
-    use onboarding_counter::ic_agent::{create_client_from_config, load_env_config, ICConfig};
+    use onboarding_counter::counter_stream::{
+        counter_stream, CounterUpdates, COUNTER_STREAM_PATH, DEFAULT_POLL_INTERVAL,
+    };
+    use onboarding_counter::ic_agent::{
+        create_client_from_config, dry_run_from_env, env_flag, extra_callers_from_env, ICConfig,
+    };
+    use onboarding_counter::json_api::counter_routes;
+    use onboarding_counter::rate_limit::{limit_api_calls, ApiRateLimits};
+    use onboarding_counter::telemetry::install_prometheus_recorder;
+    use std::sync::Arc;
 
-    let ic_config = ICConfig::default_mainnet();
-    let canister_client = create_client_from_config(&ic_config).await?;
+    let metrics_handle = install_prometheus_recorder()?;
+    let rate_limits = Arc::new(ApiRateLimits::from_env()?);
+
+    let ic_config = ICConfig::default_mainnet().with_env()?;
+    let canister_client = create_client_from_config(&ic_config)
+        .await?
+        .with_dry_run(dry_run_from_env())
+        .with_extra_callers(extra_callers_from_env()?);
+    // A replica blip at boot shouldn't keep the site down, the first calls are just slower
+    if let Err(e) = canister_client.warmup().await {
+        log!("⚠️ Canister client warmup failed, continuing anyway: {}", e);
+    }
+    // Wrong canister IDs would otherwise leave a server up whose every call fails
+    let startup_check = canister_client.caller_get().await;
+    startup_decision(&startup_check, env_flag("ALLOW_DEGRADED_START"))?;
+    match &startup_check {
+        Ok(value) => log!("✅ Caller canister answered, counter is at {}", value),
+        Err(e) => log!(
+            "⚠️ Startup check failed, serving anyway since ALLOW_DEGRADED_START is set: {}",
+            startup_diagnostic(e)
+        ),
+    }
+
+    let counter_updates = CounterUpdates::spawn(canister_client.clone(), DEFAULT_POLL_INTERVAL);
 
     println!("\n🌐 Starting Leptos web server...");
 
-    let conf = get_configuration(None).unwrap();
+    let mut conf = get_configuration(None).unwrap();
+    conf.leptos_options.site_addr = resolve_site_addr(conf.leptos_options.site_addr)?;
     let addr = conf.leptos_options.site_addr;
     let leptos_options = conf.leptos_options;
 
     let routes = generate_route_list(App);
 
     let app = Router::new()
+        .route(
+            "/metrics",
+            get(move || {
+                let metrics_handle = metrics_handle.clone();
+                async move { metrics_handle.render() }
+            }),
+        )
+        .route(
+            COUNTER_STREAM_PATH,
+            get({
+                let counter_updates = counter_updates.clone();
+                move || async move { counter_stream(&counter_updates) }
+            }),
+        )
+        .merge(counter_routes(canister_client.clone()))
         .leptos_routes_with_context(
             &leptos_options,
             routes,
@@ -29,6 +76,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 let canister_client = canister_client.clone();
                 move || {
                     provide_context(canister_client.clone());
+                    provide_context(ic_config.clone());
                 }
             },
             {
@@ -37,21 +85,268 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             },
         )
         .fallback(leptos_axum::file_and_error_handler(shell))
-        .with_state(leptos_options);
+        .with_state(leptos_options)
+        // Server functions forward to canister update calls, which cost a round of consensus each
+        .layer(axum::middleware::from_fn_with_state(
+            rate_limits,
+            limit_api_calls,
+        ));
+    let app = match compression_layer() {
+        Some(compression) => app.layer(compression),
+        None => app,
+    };
+    let app = match cors_layer()? {
+        Some(cors) => app.layer(cors),
+        None => app,
+    };
 
     // Start the server
     log!("🚀 Leptos server listening on http://{}", &addr);
     let listener = tokio::net::TcpListener::bind(&addr).await.unwrap();
-    axum::serve(listener, app.into_make_service())
-        .await
-        .unwrap();
+    // Connection info gives the rate limiter the client's address
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<std::net::SocketAddr>(),
+    )
+    .with_graceful_shutdown(shutdown_signal(counter_updates))
+    .await
+    .unwrap();
+    log!("✅ Leptos server shut down cleanly");
 
     Ok(())
 }
 
+/// Apply `HOST`/`PORT` overrides (as set by PaaS platforms) on top of the
+/// address from the Leptos configuration
+#[cfg(feature = "ssr")]
+fn resolve_site_addr(
+    configured: std::net::SocketAddr,
+) -> Result<std::net::SocketAddr, Box<dyn std::error::Error>> {
+    use leptos::logging::log;
+
+    let mut addr = configured;
+    if let Ok(host) = std::env::var("HOST") {
+        let ip = host
+            .parse()
+            .map_err(|e| format!("Invalid HOST '{}': {}", host, e))?;
+        addr.set_ip(ip);
+    }
+    if let Ok(port) = std::env::var("PORT") {
+        let port = port
+            .parse::<u16>()
+            .map_err(|e| format!("Invalid PORT '{}': {}", port, e))?;
+        addr.set_port(port);
+    }
+
+    if addr != configured {
+        log!(
+            "🔧 Overriding configured address {} with {}",
+            configured,
+            addr
+        );
+    }
+    Ok(addr)
+}
+
+/// Gzip or brotli response compression, negotiated from `Accept-Encoding`, when
+/// `ENABLE_COMPRESSION` is set. The default predicate already leaves out tiny
+/// bodies, images and the `text/event-stream` counter stream, which has to reach
+/// the browser one event at a time.
+#[cfg(feature = "ssr")]
+fn compression_layer() -> Option<tower_http::compression::CompressionLayer> {
+    use leptos::logging::log;
+    use onboarding_counter::ic_agent::env_flag;
+
+    if !env_flag("ENABLE_COMPRESSION") {
+        return None;
+    }
+    log!("🗜️ Compressing responses for clients that accept gzip or brotli");
+    Some(
+        tower_http::compression::CompressionLayer::new()
+            .gzip(true)
+            .br(true),
+    )
+}
+
+/// Whether the server may start given the startup check's `result`. A failed
+/// check only passes when `allow_degraded` (`ALLOW_DEGRADED_START`) is set.
+#[cfg(feature = "ssr")]
+fn startup_decision(
+    result: &Result<
+        onboarding_counter::ic_agent::CounterValue,
+        onboarding_counter::ic_agent::ICError,
+    >,
+    allow_degraded: bool,
+) -> Result<(), String> {
+    match result {
+        Ok(_) => Ok(()),
+        Err(_) if allow_degraded => Ok(()),
+        Err(e) => Err(format!(
+            "Startup check failed: {}. Set ALLOW_DEGRADED_START=1 to serve anyway",
+            startup_diagnostic(e)
+        )),
+    }
+}
+
+/// The startup check's failure with a hint at the usual cause
+#[cfg(feature = "ssr")]
+fn startup_diagnostic(e: &onboarding_counter::ic_agent::ICError) -> String {
+    use onboarding_counter::ic_agent::ICError;
+
+    let hint = match e {
+        ICError::CanisterNotFound { .. } | ICError::InvalidCanisterId(_) => {
+            "check COUNTER_CANISTER_ID and CALLER_CANISTER_ID for this DEPLOYMENT_ENV"
+        }
+        ICError::AgentUnavailable
+        | ICError::Transport(_)
+        | ICError::ReplicaUnreachable { .. }
+        | ICError::Timeout => "the replica couldn't be reached",
+        ICError::StillProcessing { .. } => "the replica is too slow to answer",
+        ICError::InvalidCertificate => "the replica's root key doesn't match",
+        ICError::Reject { .. } | ICError::Underflow | ICError::UnknownCounter { .. } => {
+            "the caller canister rejected the read"
+        }
+        ICError::Encode(_) | ICError::Decode(_) => {
+            "the caller canister's interface doesn't match this build"
+        }
+    };
+    format!("{} ({})", e, hint)
+}
+
+/// CORS policy for the origins listed in `CORS_ALLOWED_ORIGINS` (comma-separated).
+/// Unset means no CORS headers at all, so browsers keep the API same-origin only.
+#[cfg(feature = "ssr")]
+fn cors_layer() -> Result<Option<tower_http::cors::CorsLayer>, Box<dyn std::error::Error>> {
+    use axum::http::{header, HeaderValue, Method};
+    use leptos::logging::log;
+    use tower_http::cors::CorsLayer;
+
+    let Ok(origins) = std::env::var("CORS_ALLOWED_ORIGINS") else {
+        return Ok(None);
+    };
+    let origins = origins
+        .split(',')
+        .map(str::trim)
+        .filter(|origin| !origin.is_empty())
+        .map(|origin| {
+            origin
+                .parse::<HeaderValue>()
+                .map_err(|e| format!("Invalid CORS origin '{}': {}", origin, e))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    if origins.is_empty() {
+        return Ok(None);
+    }
+
+    log!("🔓 Allowing cross-origin requests from {:?}", origins);
+    // The layer answers preflight OPTIONS requests itself, before routing, so
+    // the POST-only server function routes don't need an OPTIONS handler
+    Ok(Some(
+        CorsLayer::new()
+            .allow_origin(origins)
+            .allow_methods([Method::GET, Method::POST])
+            .allow_headers([header::CONTENT_TYPE, header::ACCEPT]),
+    ))
+}
+
+/// Resolves on ctrl-c or SIGTERM so in-flight canister calls can finish before exit
+#[cfg(feature = "ssr")]
+async fn shutdown_signal(counter_updates: onboarding_counter::counter_stream::CounterUpdates) {
+    use leptos::logging::log;
+
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("failed to install ctrl-c handler");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("failed to install SIGTERM handler")
+            .recv()
+            .await;
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+
+    log!("🛑 Shutdown signal received, draining in-flight requests...");
+    counter_updates.close();
+}
+
 #[cfg(not(feature = "ssr"))]
 pub fn main() {
     // no client-side main function
     // unless we want this to work with e.g., Trunk for pure client-side testing
     // see lib.rs for hydration function instead
 }
+
+#[cfg(all(test, feature = "ssr"))]
+mod tests {
+    use super::*;
+    use onboarding_counter::ic_agent::{CounterValue, ICError};
+
+    #[test]
+    fn startup_passes_when_the_canister_answers() {
+        assert_eq!(startup_decision(&Ok(CounterValue(3)), false), Ok(()));
+    }
+
+    #[test]
+    fn failed_startup_check_refuses_unless_degraded_start_is_allowed() {
+        let failed = Err(ICError::CanisterNotFound {
+            canister: candid::Principal::anonymous(),
+            role: "Caller",
+            env: "prod",
+        });
+        assert_eq!(startup_decision(&failed, true), Ok(()));
+        let refused = startup_decision(&failed, false).unwrap_err();
+        assert!(refused.contains("ALLOW_DEGRADED_START=1"), "{}", refused);
+        assert!(refused.contains("check COUNTER_CANISTER_ID"), "{}", refused);
+    }
+
+    #[tokio::test]
+    async fn compression_gzips_json_but_not_the_event_stream() {
+        use axum::{body::Body, http::Request, routing::get, Router};
+        use tower::ServiceExt;
+
+        std::env::set_var("ENABLE_COMPRESSION", "1");
+        let compression = compression_layer();
+        std::env::remove_var("ENABLE_COMPRESSION");
+        let body = format!("{{\"values\": [{}]}}", vec!["42"; 100].join(", "));
+        let app = Router::new()
+            .route(
+                "/json",
+                get(move || async move { ([("content-type", "application/json")], body) }),
+            )
+            .route(
+                "/stream",
+                get(|| async {
+                    (
+                        [("content-type", "text/event-stream")],
+                        "data: 42\n\n".repeat(100),
+                    )
+                }),
+            )
+            .layer(compression.unwrap());
+
+        let encoding = |path: &'static str| {
+            let app = app.clone();
+            async move {
+                let request = Request::get(path)
+                    .header("accept-encoding", "gzip")
+                    .body(Body::empty())
+                    .unwrap();
+                let response = app.oneshot(request).await.unwrap();
+                response.headers().get("content-encoding").cloned()
+            }
+        };
+        assert_eq!(encoding("/json").await.unwrap(), "gzip");
+        assert_eq!(encoding("/stream").await, None);
+    }
+}
@@ -0,0 +1,365 @@
+#[cfg(feature = "ssr")]
+use {
+    crate::ic_agent::env_flag,
+    crate::json_api::{COUNTER_DECREMENT_PATH, COUNTER_INCREMENT_PATH, COUNTER_PATH},
+    axum::{
+        body::{to_bytes, Body},
+        extract::{ConnectInfo, Request, State},
+        http::{header, Method, StatusCode},
+        middleware::Next,
+        response::{IntoResponse, Response},
+    },
+    std::{
+        collections::HashMap,
+        net::{IpAddr, SocketAddr},
+        sync::{Arc, Mutex},
+        time::Duration,
+    },
+    web_time::Instant,
+};
+
+/// Prefix the server functions are registered under
+pub const API_PREFIX: &str = "/api/";
+
+/// Mutating server function calls per client per minute unless `API_RATE_LIMIT_PER_MIN` says otherwise
+pub const DEFAULT_WRITES_PER_MIN: u32 = 30;
+
+/// Read-only server function calls per client per minute unless
+/// `API_READ_RATE_LIMIT_PER_MIN` says otherwise. Reads are cheaper, so they get more.
+pub const DEFAULT_READS_PER_MIN: u32 = 120;
+
+/// Largest server function body buffered to tell reads from writes
+#[cfg(feature = "ssr")]
+const MAX_BODY_BYTES: usize = 64 * 1024;
+
+/// Forget clients once this many are tracked, keeping only those still limited
+#[cfg(feature = "ssr")]
+const MAX_TRACKED_CLIENTS: usize = 10_000;
+
+#[cfg(feature = "ssr")]
+const WINDOW: Duration = Duration::from_secs(60);
+
+/// Token bucket per client IP: `per_min` calls can be made back to back, and
+/// the allowance refills evenly over a minute
+#[cfg(feature = "ssr")]
+pub struct RateLimiter {
+    per_min: u32,
+    buckets: Mutex<HashMap<IpAddr, (f64, Instant)>>,
+}
+
+#[cfg(feature = "ssr")]
+impl RateLimiter {
+    pub fn new(per_min: u32) -> Self {
+        Self {
+            per_min: per_min.max(1),
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Most calls a client can make back to back
+    pub fn per_min(&self) -> u32 {
+        self.per_min
+    }
+
+    /// Take a call from `client`'s allowance, or say how long until one frees up
+    pub fn check(&self, client: IpAddr) -> Result<(), Duration> {
+        self.check_many(client, 1)
+    }
+
+    /// Take `calls` calls from `client`'s allowance at once, or say how long until
+    /// that many free up. More than [`Self::per_min`] never fits.
+    pub fn check_many(&self, client: IpAddr, calls: u32) -> Result<(), Duration> {
+        let cost = f64::from(calls);
+        let capacity = f64::from(self.per_min);
+        let refill_per_sec = capacity / WINDOW.as_secs_f64();
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() >= MAX_TRACKED_CLIENTS {
+            buckets.retain(|_, (tokens, updated)| {
+                *tokens + now.duration_since(*updated).as_secs_f64() * refill_per_sec < capacity
+            });
+        }
+
+        let (tokens, updated) = buckets.entry(client).or_insert((capacity, now));
+        *tokens =
+            (*tokens + now.duration_since(*updated).as_secs_f64() * refill_per_sec).min(capacity);
+        *updated = now;
+        if *tokens >= cost {
+            *tokens -= cost;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((cost - *tokens) / refill_per_sec))
+        }
+    }
+}
+
+/// Separate allowances for server function calls that only read the counter and
+/// those that change it
+#[cfg(feature = "ssr")]
+pub struct ApiRateLimits {
+    reads: RateLimiter,
+    writes: RateLimiter,
+    /// Key clients on `Fly-Client-IP` instead of the connection's address
+    trust_fly_client_ip: bool,
+}
+
+#[cfg(feature = "ssr")]
+impl ApiRateLimits {
+    pub fn new(reads_per_min: u32, writes_per_min: u32) -> Self {
+        Self {
+            reads: RateLimiter::new(reads_per_min),
+            writes: RateLimiter::new(writes_per_min),
+            trust_fly_client_ip: false,
+        }
+    }
+
+    /// Take the client address from the `Fly-Client-IP` header. Only safe behind
+    /// Fly's proxy, which overwrites it; anywhere else clients can pick their own.
+    pub fn with_trusted_fly_client_ip(mut self, trust: bool) -> Self {
+        self.trust_fly_client_ip = trust;
+        self
+    }
+
+    /// Limits from `API_READ_RATE_LIMIT_PER_MIN` and `API_RATE_LIMIT_PER_MIN`,
+    /// falling back to [`DEFAULT_READS_PER_MIN`] and [`DEFAULT_WRITES_PER_MIN`],
+    /// trusting `Fly-Client-IP` only if `TRUST_FLY_CLIENT_IP` is set
+    pub fn from_env() -> anyhow::Result<Self> {
+        Ok(Self::new(
+            per_min_from_env("API_READ_RATE_LIMIT_PER_MIN", DEFAULT_READS_PER_MIN)?,
+            per_min_from_env("API_RATE_LIMIT_PER_MIN", DEFAULT_WRITES_PER_MIN)?,
+        )
+        .with_trusted_fly_client_ip(env_flag("TRUST_FLY_CLIENT_IP")))
+    }
+}
+
+#[cfg(feature = "ssr")]
+fn per_min_from_env(name: &str, default: u32) -> anyhow::Result<u32> {
+    match std::env::var(name) {
+        Ok(value) => match value.trim().parse() {
+            Ok(0) | Err(_) => Err(anyhow::anyhow!(
+                "Invalid {} '{}': must be a positive number of calls per minute",
+                name,
+                value
+            )),
+            Ok(per_min) => Ok(per_min),
+        },
+        Err(_) => Ok(default),
+    }
+}
+
+/// How many updates a URL-encoded server function body asks for. Every action
+/// encodes as a form value, so `action=Get` is a read and each other action in a
+/// batch is one write; argument-less calls like `get_deployment_info` are reads
+/// too. The counter name and the export format don't change what a call does to
+/// the counter.
+#[cfg(feature = "ssr")]
+fn writes_in(body: &[u8]) -> u32 {
+    body.split(|byte| *byte == b'&')
+        .filter(|pair| {
+            !pair.is_empty() && !pair.starts_with(b"counter=") && !pair.starts_with(b"format=")
+        })
+        .filter(|pair| pair.rsplit(|byte| *byte == b'=').next() != Some(b"Get"))
+        .count()
+        .try_into()
+        .unwrap_or(u32::MAX)
+}
+
+/// Client address to rate limit by. Behind Fly's proxy every connection comes
+/// from the proxy, which reports the real client in `Fly-Client-IP`, but
+/// elsewhere that header is whatever the client sent, so it's only read when
+/// `trust_fly_client_ip` is set.
+#[cfg(feature = "ssr")]
+fn client_ip(request: &Request, peer: SocketAddr, trust_fly_client_ip: bool) -> IpAddr {
+    if !trust_fly_client_ip {
+        return peer.ip();
+    }
+    request
+        .headers()
+        .get("fly-client-ip")
+        .and_then(|ip| ip.to_str().ok())
+        .and_then(|ip| ip.parse().ok())
+        .unwrap_or(peer.ip())
+}
+
+/// Axum middleware answering 429 once a client exceeds its allowance for the
+/// server functions under [`API_PREFIX`] or the JSON counter routes. A batch
+/// costs one write per action that isn't a `Get`, and 413 if it could never fit
+/// in a minute's allowance. Everything else passes straight through.
+#[cfg(feature = "ssr")]
+pub async fn limit_api_calls(
+    State(limits): State<Arc<ApiRateLimits>>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let path = request.uri().path();
+    let client = client_ip(&request, peer, limits.trust_fly_client_ip);
+
+    let (request, writes) = if path.starts_with(API_PREFIX) {
+        let (parts, body) = request.into_parts();
+        let Ok(body) = to_bytes(body, MAX_BODY_BYTES).await else {
+            return StatusCode::PAYLOAD_TOO_LARGE.into_response();
+        };
+        let writes = writes_in(&body);
+        (Request::from_parts(parts, Body::from(body)), writes)
+    } else if [COUNTER_PATH, COUNTER_INCREMENT_PATH, COUNTER_DECREMENT_PATH].contains(&path) {
+        let writes = u32::from(request.method() != Method::GET);
+        (request, writes)
+    } else {
+        return next.run(request).await;
+    };
+
+    if writes > limits.writes.per_min() {
+        return (
+            StatusCode::PAYLOAD_TOO_LARGE,
+            format!(
+                "A call can make at most {} updates, this one asks for {}",
+                limits.writes.per_min(),
+                writes
+            ),
+        )
+            .into_response();
+    }
+    let allowed = match writes {
+        0 => limits.reads.check(client),
+        writes => limits.writes.check_many(client, writes),
+    };
+    if let Err(retry_after) = allowed {
+        return (
+            StatusCode::TOO_MANY_REQUESTS,
+            [(
+                header::RETRY_AFTER,
+                retry_after.as_secs_f64().ceil().max(1.0).to_string(),
+            )],
+            "Too many requests, slow down",
+        )
+            .into_response();
+    }
+    next.run(request).await
+}
+
+#[cfg(all(test, feature = "ssr"))]
+mod tests {
+    use super::*;
+    use axum::{routing::post, Router};
+    use tower::ServiceExt;
+
+    const CLIENT: &str = "203.0.113.7:50000";
+
+    #[test]
+    fn allows_per_min_calls_then_limits() {
+        let limiter = RateLimiter::new(3);
+        let client = IpAddr::from([203, 0, 113, 7]);
+        for _ in 0..3 {
+            assert_eq!(limiter.check(client), Ok(()));
+        }
+        let retry_after = limiter.check(client).unwrap_err();
+        assert!(retry_after > Duration::ZERO && retry_after <= WINDOW);
+        // Someone else still has their full allowance
+        assert_eq!(limiter.check(IpAddr::from([203, 0, 113, 8])), Ok(()));
+    }
+
+    #[test]
+    fn counts_every_update_in_a_body() {
+        assert_eq!(writes_in(b""), 0);
+        assert_eq!(writes_in(b"action=Get"), 0);
+        assert_eq!(writes_in(b"action=Get&counter=blue"), 0);
+        assert_eq!(writes_in(b"format=Csv"), 0);
+        assert_eq!(writes_in(b"action=Increment"), 1);
+        assert_eq!(writes_in(b"action%5BSet%5D=5"), 1);
+        assert_eq!(
+            writes_in(b"actions%5B0%5D=Increment&actions%5B1%5D=Get&actions%5B2%5D=Decrement"),
+            2
+        );
+    }
+
+    fn app(limits: ApiRateLimits) -> Router {
+        Router::new()
+            .route("/api/call", post(|| async { "ok" }))
+            .layer(axum::middleware::from_fn_with_state(
+                Arc::new(limits),
+                limit_api_calls,
+            ))
+    }
+
+    async fn call(app: &Router, body: &'static str, fly_client_ip: Option<&str>) -> StatusCode {
+        let mut request = Request::post("/api/call").body(Body::from(body)).unwrap();
+        if let Some(ip) = fly_client_ip {
+            request
+                .headers_mut()
+                .insert("fly-client-ip", ip.parse().unwrap());
+        }
+        request
+            .extensions_mut()
+            .insert(ConnectInfo(CLIENT.parse::<SocketAddr>().unwrap()));
+        app.clone().oneshot(request).await.unwrap().status()
+    }
+
+    #[tokio::test]
+    async fn write_past_the_allowance_gets_429_while_reads_still_pass() {
+        let app = app(ApiRateLimits::new(5, 2));
+        assert_eq!(call(&app, "action=Increment", None).await, StatusCode::OK);
+        assert_eq!(call(&app, "action=Decrement", None).await, StatusCode::OK);
+        assert_eq!(
+            call(&app, "action=Increment", None).await,
+            StatusCode::TOO_MANY_REQUESTS
+        );
+        assert_eq!(call(&app, "action=Get", None).await, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn batch_costs_one_write_per_update() {
+        let app = app(ApiRateLimits::new(5, 3));
+        assert_eq!(
+            call(
+                &app,
+                "actions%5B0%5D=Increment&actions%5B1%5D=Increment",
+                None
+            )
+            .await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            call(
+                &app,
+                "actions%5B0%5D=Increment&actions%5B1%5D=Increment",
+                None
+            )
+            .await,
+            StatusCode::TOO_MANY_REQUESTS
+        );
+        assert_eq!(
+            call(
+                &app,
+                "actions%5B0%5D=Increment&actions%5B1%5D=Increment&actions%5B2%5D=Increment&actions%5B3%5D=Increment",
+                None
+            )
+            .await,
+            StatusCode::PAYLOAD_TOO_LARGE
+        );
+    }
+
+    #[tokio::test]
+    async fn fly_client_ip_is_ignored_unless_trusted() {
+        let app = app(ApiRateLimits::new(5, 1));
+        assert_eq!(
+            call(&app, "action=Increment", Some("198.51.100.1")).await,
+            StatusCode::OK
+        );
+        // A new header value doesn't buy a new allowance
+        assert_eq!(
+            call(&app, "action=Increment", Some("198.51.100.2")).await,
+            StatusCode::TOO_MANY_REQUESTS
+        );
+
+        let app = self::app(ApiRateLimits::new(5, 1).with_trusted_fly_client_ip(true));
+        assert_eq!(
+            call(&app, "action=Increment", Some("198.51.100.1")).await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            call(&app, "action=Increment", Some("198.51.100.2")).await,
+            StatusCode::OK
+        );
+    }
+}
@@ -1,71 +1,447 @@
 use leptos::prelude::*;
+use leptos::server_fn::{
+    codec::JsonEncoding,
+    error::{FromServerFnError, ServerFnErrorErr},
+};
 use serde::{Deserialize, Serialize};
+use std::time::Duration;
 
 
-use crate::ic_agent::{ICConfig,ICClient};
+use crate::ic_agent::{CounterValue, ExportFormat, ICError};
+#[cfg(feature = "ssr")]
+use {
+    crate::ic_agent::{ICClient, ICConfig},
+    web_time::Instant,
+};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum CallerAction {
     Get,
     Increment,
     Decrement,
+    Set(u64),
+    Batch(Vec<CallerAction>),
+}
+
+impl std::fmt::Display for CallerAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CallerAction::Get => write!(f, "Get"),
+            CallerAction::Increment => write!(f, "Increment"),
+            CallerAction::Decrement => write!(f, "Decrement"),
+            CallerAction::Set(value) => write!(f, "Set to {}", value),
+            CallerAction::Batch(actions) => write!(f, "Batch of {}", actions.len()),
+        }
+    }
+}
+
+/// Which of the failure modes a failed call hit, so the UI can react differently
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CallerErrorKind {
+    /// Network or agent failure; retrying may help
+    Transport,
+    /// The canister or replica said no; the reason should be shown as-is
+    Reject,
+    /// The arguments or the canister's answer didn't have the expected candid type
+    Decode,
+    /// The update may still apply even though we stopped waiting
+    Timeout,
+    /// The server isn't set up to make the call, e.g. its client is missing from
+    /// context; nothing the user does will fix it
+    Unavailable,
+}
+
+impl From<&ICError> for CallerErrorKind {
+    fn from(e: &ICError) -> Self {
+        match e {
+            ICError::AgentUnavailable
+            | ICError::Transport(_)
+            | ICError::ReplicaUnreachable { .. }
+            | ICError::InvalidCertificate => CallerErrorKind::Transport,
+            ICError::Reject { .. }
+            | ICError::Underflow
+            | ICError::CanisterNotFound { .. }
+            | ICError::UnknownCounter { .. }
+            | ICError::InvalidCanisterId(_) => CallerErrorKind::Reject,
+            ICError::Encode(_) | ICError::Decode(_) => CallerErrorKind::Decode,
+            ICError::Timeout | ICError::StillProcessing { .. } => CallerErrorKind::Timeout,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CallerResult {
-    pub value: String,
+    pub value: CounterValue,
     pub success: bool,
     pub error: Option<String>,
+    pub kind: Option<CallerErrorKind>,
     pub action: CallerAction,
+    /// How long the canister call took, as seen by whoever made it
+    pub duration_ms: Option<u64>,
+    /// Value read just before a successful update, see [`crate::ic_agent::ICClient::value_before`]
+    pub previous_value: Option<CounterValue>,
+    /// Tags the server's log lines for this call, for users to quote when reporting a failure
+    pub request_id: Option<String>,
+    /// Made without an identity, so the canister can't attribute the change to anyone
+    #[serde(default)]
+    pub anonymous: bool,
+}
+
+impl CallerResult {
+    pub fn from_call(action: CallerAction, result: Result<CounterValue, ICError>) -> Self {
+        match result {
+            Ok(value) => CallerResult {
+                value,
+                success: true,
+                error: None,
+                kind: None,
+                action,
+                duration_ms: None,
+                previous_value: None,
+                request_id: None,
+                anonymous: false,
+            },
+            Err(e) => CallerResult::failed(action, &e),
+        }
+    }
+
+    /// The failed result `action` would have had, from what [`execute_counter_action`]
+    /// failed with
+    pub fn from_error(action: CallerAction, e: &CounterActionError) -> Self {
+        CallerResult {
+            value: CounterValue::default(),
+            success: false,
+            error: Some(e.error.message.clone()),
+            kind: Some(e.kind()),
+            action,
+            duration_ms: None,
+            previous_value: None,
+            request_id: e.error.request_id.clone(),
+            anonymous: false,
+        }
+    }
+
+    pub fn failed(action: CallerAction, e: &ICError) -> Self {
+        CallerResult {
+            value: CounterValue::default(),
+            success: false,
+            error: Some(failure_message(e)),
+            kind: Some(e.into()),
+            action,
+            duration_ms: None,
+            previous_value: None,
+            request_id: None,
+            anonymous: false,
+        }
+    }
+
+    pub fn with_duration(mut self, elapsed: Duration) -> Self {
+        self.duration_ms = Some(elapsed.as_millis() as u64);
+        self
+    }
+
+    /// Record the value read before the call, kept only if the call succeeded
+    pub fn with_previous_value(mut self, previous_value: Option<CounterValue>) -> Self {
+        self.previous_value = previous_value.filter(|_| self.success);
+        self
+    }
+
+    pub fn with_request_id(mut self, request_id: String) -> Self {
+        self.request_id = Some(request_id);
+        self
+    }
+
+    pub fn with_anonymous(mut self, anonymous: bool) -> Self {
+        self.anonymous = anonymous;
+        self
+    }
+}
+
+/// Rejects carry the canister's own reason, show it verbatim
+fn failure_message(e: &ICError) -> String {
+    match e {
+        ICError::Reject { message, .. } => message.clone(),
+        e => e.to_string(),
+    }
+}
+
+/// What [`execute_counter_action`] fails with, serialized as
+/// `{"error": {"kind": "Reject", "message": "..."}}` so JSON consumers and the
+/// buttons read the same shape
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CounterActionError {
+    pub error: CounterActionErrorDetail,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CounterActionErrorDetail {
+    pub kind: CallerErrorKind,
+    pub message: String,
+    /// Set once the call was made, see [`CallerResult::request_id`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+}
+
+impl CounterActionError {
+    pub fn new(kind: CallerErrorKind, message: impl Into<String>) -> Self {
+        CounterActionError {
+            error: CounterActionErrorDetail {
+                kind,
+                message: message.into(),
+                request_id: None,
+            },
+        }
+    }
+
+    pub fn with_request_id(mut self, request_id: String) -> Self {
+        self.error.request_id = Some(request_id);
+        self
+    }
+
+    pub fn kind(&self) -> CallerErrorKind {
+        self.error.kind
+    }
+
+    /// Whether the call reached the canister, as opposed to failing before it
+    pub fn call_made(&self) -> bool {
+        self.error.request_id.is_some()
+    }
+}
+
+impl std::fmt::Display for CounterActionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.error.message)
+    }
+}
+
+impl std::error::Error for CounterActionError {}
+
+impl From<&ICError> for CounterActionError {
+    fn from(e: &ICError) -> Self {
+        CounterActionError::new(e.into(), failure_message(e))
+    }
+}
+
+impl FromServerFnError for CounterActionError {
+    type Encoder = JsonEncoding;
+
+    fn from_server_fn_error(value: ServerFnErrorErr) -> Self {
+        let kind = match value {
+            // Never reached the server function, so trying again may work
+            ServerFnErrorErr::Request(_)
+            | ServerFnErrorErr::MiddlewareError(_)
+            | ServerFnErrorErr::Response(_) => CallerErrorKind::Transport,
+            ServerFnErrorErr::Args(_)
+            | ServerFnErrorErr::MissingArg(_)
+            | ServerFnErrorErr::Serialization(_)
+            | ServerFnErrorErr::Deserialization(_) => CallerErrorKind::Decode,
+            ServerFnErrorErr::Registration(_) => CallerErrorKind::Unavailable,
+            ServerFnErrorErr::UnsupportedRequestMethod(_)
+            | ServerFnErrorErr::ServerError(_) => CallerErrorKind::Reject,
+        };
+        CounterActionError::new(kind, value.to_string())
+    }
+}
+
+/// Random UUID (version 4) tying a server function call to its log lines
+#[cfg(feature = "ssr")]
+fn new_request_id() -> String {
+    uuid::Uuid::new_v4().to_string()
+}
+
+
+
+/// The server's shared client. A route registered without it in context gets a
+/// clean error instead of a panic in the request handler.
+#[cfg(feature = "ssr")]
+fn server_client() -> Result<ICClient, ServerFnError<String>> {
+    use_context::<ICClient>()
+        .ok_or_else(|| ServerFnError::ServerError("IC client not configured on server".to_string()))
+}
+
+/// Which environment and canisters the server-side buttons talk to
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeploymentInfo {
+    pub env: String,
+    pub counter_canister_id: String,
+    pub caller_canister_id: String,
+    /// Updates are only logged, see [`crate::ic_agent::ICClient::with_dry_run`]
+    pub dry_run: bool,
+    /// Names [`execute_counter_action`] accepts besides the default counter
+    pub counters: Vec<String>,
+    /// The server calls without an identity, see [`crate::ic_agent::ICClient::is_anonymous`]
+    pub anonymous: bool,
+}
+
+#[server(GetDeploymentInfo, "/api")]
+pub async fn get_deployment_info() -> Result<DeploymentInfo, ServerFnError<String>> {
+    #[cfg(feature = "ssr")]
+    {
+        let config = use_context::<ICConfig>().ok_or_else(|| {
+            ServerFnError::ServerError("IC config not provided on server".to_string())
+        })?;
+        let client = server_client()?;
+        let (counter_canister_id, caller_canister_id) = client.get_canister_ids();
+        Ok(DeploymentInfo {
+            env: config.deployment_env,
+            counter_canister_id: counter_canister_id.to_text(),
+            caller_canister_id: caller_canister_id.to_text(),
+            dry_run: client.is_dry_run(),
+            counters: client.counter_names(),
+            anonymous: client.is_anonymous(),
+        })
+    }
+    #[cfg(not(feature = "ssr"))]
+    {
+        // On client side, return a placeholder response
+        Err(ServerFnError::ServerError(
+            "Server function called on client side".to_string(),
+        ))
+    }
 }
 
+/// What one caller canister reports for the counter, see [`compare_callers`]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CallerView {
+    pub caller_canister_id: String,
+    pub value: Option<CounterValue>,
+    pub error: Option<String>,
+}
 
+#[server(CompareCallers, "/api")]
+pub async fn compare_callers() -> Result<Vec<CallerView>, ServerFnError<String>> {
+    #[cfg(feature = "ssr")]
+    {
+        let client = server_client()?;
+        Ok(client
+            .compare_callers()
+            .await
+            .into_iter()
+            .map(|(caller, result)| CallerView {
+                caller_canister_id: caller.to_text(),
+                value: result.as_ref().ok().copied(),
+                error: result.err().map(|e| e.to_string()),
+            })
+            .collect())
+    }
+    #[cfg(not(feature = "ssr"))]
+    {
+        // On client side, return a placeholder response
+        Err(ServerFnError::ServerError(
+            "Server function called on client side".to_string(),
+        ))
+    }
+}
 
 #[server(ExecuteCallerAction, "/api")]
 pub async fn execute_counter_action(
     action: CallerAction,
-) -> Result<CallerResult, ServerFnError<String>> {
+    /// One of [`DeploymentInfo::counters`], or the default counter if `None`
+    counter: Option<String>,
+) -> Result<CallerResult, CounterActionError> {
     #[cfg(feature = "ssr")]
     {
-        let client = expect_context::<ICClient>();
-        match action {
-            CallerAction::Get => {
-                let value = client
-                    .caller_get()
-                    .await
-                    .map_err(|e| ServerFnError::ServerError(e.to_string()))?;
-                Ok(CallerResult {
-                    value: value,
-                    success: true,
-                    error: None,
-                    action,
-                })
-            }
-            CallerAction::Increment => {
-                let value = client
-                    .caller_increment()
-                    .await
-                    .map_err(|e| ServerFnError::ServerError(e.to_string()))?;
-                Ok(CallerResult {
-                    value: value,
-                    success: true,
-                    error: None,
-                    action,
-                })
-            }
-            CallerAction::Decrement => {
-                let value = client
-                    .caller_decrement()
-                    .await
-                    .map_err(|e| ServerFnError::ServerError(e.to_string()))?;
-                Ok(CallerResult {
-                    value: value,
-                    success: true,
-                    error: None,
-                    action,
-                })
+        let request_id = new_request_id();
+        leptos::logging::log!("[{}] {} requested", request_id, action);
+        let client = server_client()
+            .map_err(|e| {
+                let message = match e {
+                    ServerFnError::ServerError(message) => message,
+                    e => e.to_string(),
+                };
+                CounterActionError::new(CallerErrorKind::Unavailable, message)
+            })?
+            .with_request_id(&request_id);
+        let client = match counter.as_deref().map(|name| client.for_counter(name)) {
+            Some(Ok(named)) => named,
+            Some(Err(e)) => return Err(CounterActionError::from(&e)),
+            None => client,
+        };
+        let previous_value = client.value_before(&action).await;
+        let started = Instant::now();
+        let result = match action {
+            CallerAction::Batch(ref actions) => {
+                // Summarise the batch as its last value, flagging where it stopped
+                let batch = client.caller_batch(actions).await;
+                match batch.failed {
+                    Some(failed) => {
+                        let e = CounterActionError::from(&failed.error);
+                        Err(CounterActionError::new(
+                            e.kind(),
+                            format!("Action {} failed: {}", failed.index, e),
+                        ))
+                    }
+                    None => Ok(batch.values.last().copied().unwrap_or_default()),
+                }
             }
+            ref action => client
+                .caller_action(action)
+                .await
+                .map_err(|e| CounterActionError::from(&e)),
+        };
+        match result {
+            Ok(value) => Ok(CallerResult::from_call(action, Ok(value))
+                .with_duration(started.elapsed())
+                .with_previous_value(previous_value)
+                .with_request_id(request_id)
+                .with_anonymous(client.is_anonymous())),
+            Err(e) => Err(e.with_request_id(request_id)),
+        }
+    }
+    #[cfg(not(feature = "ssr"))]
+    {
+        // On client side, return a placeholder response
+        Err(CounterActionError::from_server_fn_error(
+            ServerFnErrorErr::ServerError("Server function called on client side".to_string()),
+        ))
+    }
+}
+
+/// The counter's current value with the canister IDs and a timestamp, as a file body
+#[server(ExportCounterState, "/api")]
+pub async fn export_counter_state(format: ExportFormat) -> Result<String, ServerFnError<String>> {
+    #[cfg(feature = "ssr")]
+    {
+        server_client()?
+            .export_state(format)
+            .await
+            .map_err(|e| ServerFnError::ServerError(e.to_string()))
+    }
+    #[cfg(not(feature = "ssr"))]
+    {
+        // On client side, return a placeholder response
+        Err(ServerFnError::ServerError(
+            "Server function called on client side".to_string(),
+        ))
+    }
+}
+
+#[server(ExecuteCallerBatch, "/api")]
+pub async fn execute_counter_batch(
+    actions: Vec<CallerAction>,
+) -> Result<Vec<CallerResult>, ServerFnError<String>> {
+    #[cfg(feature = "ssr")]
+    {
+        let client = server_client()?;
+        let batch = client.caller_batch(&actions).await;
+
+        // One result per action that ran; a failure is the last entry and
+        // everything after it is left out
+        let mut results: Vec<CallerResult> = actions
+            .iter()
+            .zip(batch.values)
+            .map(|(action, value)| {
+                CallerResult::from_call(action.clone(), Ok(value))
+                    .with_anonymous(client.is_anonymous())
+            })
+            .collect();
+        if let Some(failed) = batch.failed {
+            results.push(
+                CallerResult::failed(actions[failed.index].clone(), &failed.error)
+                    .with_anonymous(client.is_anonymous()),
+            );
         }
+        Ok(results)
     }
     #[cfg(not(feature = "ssr"))]
     {
@@ -75,3 +451,80 @@ pub async fn execute_counter_action(
         ))
     }
 }
+
+#[cfg(all(test, feature = "ssr"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn request_ids_are_distinct_v4_uuids() {
+        let first = uuid::Uuid::parse_str(&new_request_id()).unwrap();
+        let second = uuid::Uuid::parse_str(&new_request_id()).unwrap();
+        assert_eq!(first.get_version(), Some(uuid::Version::Random));
+        assert_eq!(first.get_variant(), uuid::Variant::RFC4122);
+        assert_ne!(first, second);
+    }
+
+    #[test]
+    fn counter_action_errors_round_trip_as_json() {
+        let error = CounterActionError::new(CallerErrorKind::Reject, "Counter underflow");
+        let json = serde_json::to_value(&error).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({"error": {"kind": "Reject", "message": "Counter underflow"}})
+        );
+        assert_eq!(serde_json::from_value::<CounterActionError>(json).unwrap(), error);
+
+        let error = error.with_request_id("abc".to_string());
+        let json = serde_json::to_value(&error).unwrap();
+        assert_eq!(json["error"]["request_id"], "abc");
+        assert_eq!(serde_json::from_value::<CounterActionError>(json).unwrap(), error);
+    }
+
+    #[tokio::test]
+    async fn missing_server_client_is_unavailable() {
+        let owner = Owner::new();
+        owner.set();
+        let error = execute_counter_action(CallerAction::Get, None)
+            .await
+            .unwrap_err();
+        assert_eq!(error.kind(), CallerErrorKind::Unavailable);
+        assert!(!error.call_made());
+    }
+
+    #[tokio::test]
+    async fn missing_server_client_is_a_clean_server_error() {
+        let owner = Owner::new();
+        owner.set();
+        let error = export_counter_state(ExportFormat::Json).await.unwrap_err();
+        assert_eq!(
+            error,
+            ServerFnError::ServerError("IC client not configured on server".to_string())
+        );
+        let error = execute_counter_action(CallerAction::Get, None)
+            .await
+            .unwrap_err();
+        assert_eq!(error.to_string(), "IC client not configured on server");
+    }
+
+    #[tokio::test]
+    async fn failed_calls_are_errors_with_their_request_id() {
+        let replica = crate::test_replica::FakeReplica::start().await;
+        let owner = Owner::new();
+        owner.set();
+        provide_context(replica.client().build().await.unwrap());
+
+        let error = execute_counter_action(CallerAction::Decrement, None)
+            .await
+            .unwrap_err();
+        assert_eq!(error.kind(), CallerErrorKind::Reject);
+        assert!(error.call_made());
+        assert_eq!(error.to_string(), ICError::Underflow.to_string());
+
+        let result = execute_counter_action(CallerAction::Increment, None)
+            .await
+            .unwrap();
+        assert!(result.success);
+        assert_eq!(result.value, CounterValue(1));
+    }
+}
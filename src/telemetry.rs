@@ -0,0 +1,106 @@
+use std::time::Duration;
+
+#[cfg(feature = "ssr")]
+use metrics_exporter_prometheus::{Matcher, PrometheusBuilder, PrometheusHandle};
+
+/// Total canister calls made through `ICClient`, labeled by method and outcome
+pub const CALLS_TOTAL: &str = "ic_client_calls_total";
+/// Failed canister calls, labeled by method and outcome
+pub const ERRORS_TOTAL: &str = "ic_client_errors_total";
+/// Canister call latency in seconds, labeled by method
+pub const CALL_DURATION_SECONDS: &str = "ic_client_call_duration_seconds";
+
+/// How a canister call ended, used as the `outcome` label
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CallOutcome {
+    /// The call succeeded and the response decoded
+    Ok,
+    /// The replica or the canister rejected the call
+    Reject,
+    /// The call never produced a usable response (network, agent or decode failure)
+    Transport,
+}
+
+impl CallOutcome {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CallOutcome::Ok => "ok",
+            CallOutcome::Reject => "reject",
+            CallOutcome::Transport => "transport",
+        }
+    }
+}
+
+/// Record a single canister call
+pub fn record_call(method: &str, outcome: CallOutcome, elapsed: Duration) {
+    let method = method.to_string();
+    let outcome_label = outcome.as_str();
+
+    metrics::counter!(CALLS_TOTAL, "method" => method.clone(), "outcome" => outcome_label)
+        .increment(1);
+    if outcome != CallOutcome::Ok {
+        metrics::counter!(ERRORS_TOTAL, "method" => method.clone(), "outcome" => outcome_label)
+            .increment(1);
+    }
+    metrics::histogram!(CALL_DURATION_SECONDS, "method" => method).record(elapsed.as_secs_f64());
+}
+
+/// Install the global Prometheus recorder and return a handle for rendering `/metrics`
+#[cfg(feature = "ssr")]
+pub fn install_prometheus_recorder() -> anyhow::Result<PrometheusHandle> {
+    // Update calls go through consensus, so buckets stretch well past a second
+    const LATENCY_BUCKETS: &[f64] = &[0.05, 0.1, 0.25, 0.5, 1.0, 2.0, 4.0, 8.0, 16.0, 32.0];
+
+    let handle = PrometheusBuilder::new()
+        .set_buckets_for_metric(
+            Matcher::Full(CALL_DURATION_SECONDS.to_string()),
+            LATENCY_BUCKETS,
+        )?
+        .install_recorder()?;
+
+    metrics::describe_counter!(CALLS_TOTAL, "Canister calls made through ICClient");
+    metrics::describe_counter!(ERRORS_TOTAL, "Failed canister calls made through ICClient");
+    metrics::describe_histogram!(
+        CALL_DURATION_SECONDS,
+        metrics::Unit::Seconds,
+        "Canister call latency"
+    );
+
+    Ok(handle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use metrics_exporter_prometheus::PrometheusBuilder;
+
+    /// Prometheus text for whatever `record` reports, without touching the global recorder
+    fn render(record: impl FnOnce()) -> String {
+        let recorder = PrometheusBuilder::new().build_recorder();
+        let handle = recorder.handle();
+        metrics::with_local_recorder(&recorder, record);
+        handle.render()
+    }
+
+    #[test]
+    fn successful_call_counts_as_ok() {
+        let rendered = render(|| {
+            record_call("call_get", CallOutcome::Ok, Duration::from_millis(20));
+            record_call("call_get", CallOutcome::Ok, Duration::from_millis(30));
+        });
+        assert!(rendered.contains(r#"ic_client_calls_total{method="call_get",outcome="ok"} 2"#));
+        assert!(!rendered.contains(ERRORS_TOTAL));
+        assert!(rendered.contains(r#"ic_client_call_duration_seconds_count{method="call_get"} 2"#));
+    }
+
+    #[test]
+    fn failed_call_counts_as_error() {
+        let rendered = render(|| {
+            record_call("call_decrement", CallOutcome::Reject, Duration::ZERO);
+        });
+        assert!(rendered
+            .contains(r#"ic_client_calls_total{method="call_decrement",outcome="reject"} 1"#));
+        assert!(rendered
+            .contains(r#"ic_client_errors_total{method="call_decrement",outcome="reject"} 1"#));
+    }
+}
@@ -0,0 +1,460 @@
+//! A replica in a test's own process, serving just enough of the HTTP interface for
+//! [`crate::ic_agent::ICClient`] to run against the real agent: the status endpoint,
+//! update calls answered with certificates signed by its own root key, queries
+//! and `read_state` for updates still running. The
+//! caller canister behind it keeps one counter; any method can be given scripted
+//! replies instead.
+
+use axum::{
+    body::Bytes,
+    extract::{ConnectInfo, Path, State},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Router,
+};
+use candid::{Nat, Principal};
+use ic_agent::{
+    agent::{Envelope, EnvelopeContent, RejectCode},
+    hash_tree::{fork, label, leaf, HashTree, Label},
+    Certificate, RequestId, TransportCallResponse,
+};
+use ic_verify_bls_signature::PrivateKey;
+use serde_cbor::Value;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// DER header of a BLS12-381 public key, as the agent expects root keys
+const DER_PREFIX: &[u8] = b"\x30\x81\x82\x30\x1d\x06\x0d\x2b\x06\x01\x04\x01\x82\xdc\x7c\x05\x03\x01\x02\x01\x06\x0c\x2b\x06\x01\x04\x01\x82\xdc\x7c\x05\x03\x02\x01\x03\x61\x00";
+
+const IC_STATE_ROOT_DOMAIN_SEPARATOR: &[u8] = b"\x0Dic-state-root";
+
+/// How long an update spends between reading the counter and writing it back, so
+/// updates that aren't serialized overlap and lose writes
+const UPDATE_DELAY: Duration = Duration::from_millis(5);
+
+pub const COUNTER_CANISTER: &str = "rrkah-fqaaa-aaaaa-aaaaq-cai";
+pub const CALLER_CANISTER: &str = "ryjl3-tyaaa-aaaaa-aaaba-cai";
+
+/// What the replica answers a call with
+#[derive(Clone, Debug)]
+pub enum Reply {
+    /// Candid-encoded reply
+    Value(Vec<u8>),
+    Reject(RejectCode, String),
+    /// Accept the update and never finish it
+    Processing,
+    /// Fail the HTTP request with this status
+    Http(u16),
+}
+
+impl Reply {
+    /// The caller canister's `Ok` reply carrying `value`
+    pub fn counter(value: u64) -> Self {
+        Reply::Value(candid::encode_one(Ok::<Nat, String>(Nat::from(value))).unwrap())
+    }
+}
+
+/// An update the replica received
+struct Call {
+    method: String,
+    /// Candid-encoded
+    arg: Vec<u8>,
+    headers: HeaderMap,
+}
+
+struct Replica {
+    /// Signs certificates
+    key: Mutex<PrivateKey>,
+    /// Reported by the status endpoint as the root key
+    announced_key: Mutex<PrivateKey>,
+    value: AtomicU64,
+    in_flight: AtomicUsize,
+    max_in_flight: AtomicUsize,
+    /// Client ends of the connections calls came in on
+    peers: Mutex<HashSet<SocketAddr>>,
+    status_fetches: AtomicUsize,
+    /// Updates received, in order
+    calls: Mutex<Vec<Call>>,
+    /// Methods of the queries received, in order
+    queries: Mutex<Vec<String>>,
+    /// First label of the first path of each `read_state` received, in order
+    read_states: Mutex<Vec<String>>,
+    /// Replies to give each method before falling back to the counter; the last
+    /// one is repeated
+    scripted: Mutex<HashMap<String, VecDeque<Reply>>>,
+}
+
+/// Handle to a running fake replica. It stops with the test's runtime.
+#[derive(Clone)]
+pub struct FakeReplica {
+    url: String,
+    replica: Arc<Replica>,
+}
+
+impl FakeReplica {
+    pub async fn start() -> Self {
+        let replica = Arc::new(Replica {
+            key: Mutex::new(PrivateKey::deserialize(&[7; 32]).unwrap()),
+            announced_key: Mutex::new(PrivateKey::deserialize(&[7; 32]).unwrap()),
+            value: AtomicU64::new(0),
+            in_flight: AtomicUsize::new(0),
+            max_in_flight: AtomicUsize::new(0),
+            peers: Mutex::new(HashSet::new()),
+            status_fetches: AtomicUsize::new(0),
+            calls: Mutex::new(Vec::new()),
+            queries: Mutex::new(Vec::new()),
+            read_states: Mutex::new(Vec::new()),
+            scripted: Mutex::new(HashMap::new()),
+        });
+        let app = Router::new()
+            .route("/api/v2/status", get(status))
+            .route("/api/v3/canister/{canister}/call", post(call))
+            .route("/api/v2/canister/{canister}/query", post(query))
+            .route("/api/v2/canister/{canister}/read_state", post(read_state))
+            .with_state(replica.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let app = app.into_make_service_with_connect_info::<SocketAddr>();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        Self { url, replica }
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// A client builder pointed at this replica and its canisters
+    pub fn client(&self) -> crate::ic_agent::ICClientBuilder {
+        crate::ic_agent::ICClient::builder()
+            .replica_url(&self.url)
+            .counter_canister(COUNTER_CANISTER)
+            .caller_canister(CALLER_CANISTER)
+    }
+
+    pub fn set_value(&self, value: u64) {
+        self.replica.value.store(value, Ordering::SeqCst);
+    }
+
+    pub fn value(&self) -> u64 {
+        self.replica.value.load(Ordering::SeqCst)
+    }
+
+    /// Most updates that were being handled at the same time
+    pub fn max_in_flight(&self) -> usize {
+        self.replica.max_in_flight.load(Ordering::SeqCst)
+    }
+
+    /// How many connections calls came in on
+    pub fn connections(&self) -> usize {
+        self.replica.peers.lock().unwrap().len()
+    }
+
+    /// How often the status endpoint, which carries the root key, was read
+    pub fn status_fetches(&self) -> usize {
+        self.replica.status_fetches.load(Ordering::SeqCst)
+    }
+
+    /// Come back with a new root key derived from `seed`, like a restarted dfx
+    pub fn restart_with_key(&self, seed: u8) {
+        let key = PrivateKey::deserialize(&[seed; 32]).unwrap();
+        *self.replica.key.lock().unwrap() = key;
+        *self.replica.announced_key.lock().unwrap() = key;
+    }
+
+    /// Sign with a key derived from `seed` while still reporting the old one, so
+    /// no refetch of the root key can fix verification
+    pub fn sign_with_unannounced_key(&self, seed: u8) {
+        *self.replica.key.lock().unwrap() = PrivateKey::deserialize(&[seed; 32]).unwrap();
+    }
+
+    /// Methods of the updates received, in order
+    pub fn calls(&self) -> Vec<String> {
+        let calls = self.replica.calls.lock().unwrap();
+        calls.iter().map(|call| call.method.clone()).collect()
+    }
+
+    /// Methods of the queries received, in order
+    pub fn queries(&self) -> Vec<String> {
+        self.replica.queries.lock().unwrap().clone()
+    }
+
+    /// What the `read_state` requests received asked for, by the first label of
+    /// their first path, e.g. `request_status` or `subnet`
+    pub fn read_states(&self) -> Vec<String> {
+        self.replica.read_states.lock().unwrap().clone()
+    }
+
+    /// Candid arguments of the updates to `method` received, in order
+    pub fn args_of(&self, method: &str) -> Vec<Vec<u8>> {
+        let calls = self.replica.calls.lock().unwrap();
+        calls
+            .iter()
+            .filter(|call| call.method == method)
+            .map(|call| call.arg.clone())
+            .collect()
+    }
+
+    /// HTTP headers of the updates to `method` received, in order
+    pub fn headers_of(&self, method: &str) -> Vec<HeaderMap> {
+        let calls = self.replica.calls.lock().unwrap();
+        calls
+            .iter()
+            .filter(|call| call.method == method)
+            .map(|call| call.headers.clone())
+            .collect()
+    }
+
+    /// Answer `method` with `replies` in order, then keep giving the last one
+    pub fn script(&self, method: &str, replies: impl IntoIterator<Item = Reply>) {
+        self.replica
+            .scripted
+            .lock()
+            .unwrap()
+            .insert(method.to_string(), replies.into_iter().collect());
+    }
+}
+
+impl Replica {
+    fn scripted(&self, method: &str) -> Option<Reply> {
+        let mut scripted = self.scripted.lock().unwrap();
+        let replies = scripted.get_mut(method)?;
+        if replies.len() > 1 {
+            replies.pop_front()
+        } else {
+            replies.front().cloned()
+        }
+    }
+
+    /// The counter behind the caller canister's `call_*` methods and the counter
+    /// canister's `get` query
+    async fn counter(&self, method: &str, arg: &[u8]) -> Reply {
+        let current = self.value.load(Ordering::SeqCst);
+        let next = match method {
+            "get" => return Reply::Value(candid::encode_one(Nat::from(current)).unwrap()),
+            "call_get" => Ok(current),
+            "call_increment" | "call_increment_idempotent" => Ok(current + 1),
+            "call_decrement" => current
+                .checked_sub(1)
+                .ok_or_else(|| "Counter underflow".to_string()),
+            "call_set" => {
+                let (_, value): (Principal, Nat) = candid::decode_args(arg).unwrap();
+                Ok(u64::try_from(&value.0).unwrap())
+            }
+            _ => {
+                return Reply::Reject(
+                    RejectCode::CanisterError,
+                    format!("Canister has no update method '{}'", method),
+                )
+            }
+        };
+        if method != "call_get" {
+            tokio::time::sleep(UPDATE_DELAY).await;
+            if let Ok(value) = next {
+                self.value.store(value, Ordering::SeqCst);
+            }
+        }
+        let next: Result<Nat, String> = next.map(Nat::from);
+        Reply::Value(candid::encode_one(next).unwrap())
+    }
+
+    /// A certificate over `tree` plus the current time, signed with the root key
+    fn certificate(&self, tree: HashTree<Vec<u8>>) -> Vec<u8> {
+        let tree = fork(tree, label("time", leaf(leb128(now_nanos()))));
+        let mut message = IC_STATE_ROOT_DOMAIN_SEPARATOR.to_vec();
+        message.extend_from_slice(&tree.digest());
+        let certificate = Certificate {
+            tree,
+            signature: self.key.lock().unwrap().sign(&message).serialize().to_vec(),
+            delegation: None,
+        };
+        serde_cbor::to_vec(&certificate).unwrap()
+    }
+
+    fn status_certificate(
+        &self,
+        request_id: &[u8],
+        status: &str,
+        reply: Option<&Reply>,
+    ) -> Vec<u8> {
+        let status = label("status", leaf(status.as_bytes().to_vec()));
+        let entries = match reply {
+            Some(Reply::Value(arg)) => fork(label("reply", leaf(arg.clone())), status),
+            Some(Reply::Reject(code, message)) => fork(
+                label("reject_code", leaf(leb128(*code as u64))),
+                fork(
+                    label("reject_message", leaf(message.as_bytes().to_vec())),
+                    status,
+                ),
+            ),
+            _ => status,
+        };
+        self.certificate(label("request_status", label(request_id.to_vec(), entries)))
+    }
+}
+
+fn now_nanos() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_nanos() as u64
+}
+
+fn leb128(mut value: u64) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            bytes.push(byte);
+            return bytes;
+        }
+        bytes.push(byte | 0x80);
+    }
+}
+
+fn cbor(value: Value) -> Response {
+    (
+        [("content-type", "application/cbor")],
+        serde_cbor::to_vec(&value).unwrap(),
+    )
+        .into_response()
+}
+
+fn text(key: &str) -> Value {
+    Value::Text(key.to_string())
+}
+
+fn envelope(body: &[u8]) -> EnvelopeContent {
+    let envelope: Envelope = serde_cbor::from_slice(body).unwrap();
+    envelope.content.into_owned()
+}
+
+async fn status(State(replica): State<Arc<Replica>>) -> Response {
+    replica.status_fetches.fetch_add(1, Ordering::SeqCst);
+    let mut root_key = DER_PREFIX.to_vec();
+    let announced_key = *replica.announced_key.lock().unwrap();
+    root_key.extend_from_slice(&announced_key.public_key().serialize());
+    cbor(Value::Map(BTreeMap::from([(
+        text("root_key"),
+        Value::Bytes(root_key),
+    )])))
+}
+
+async fn call(
+    State(replica): State<Arc<Replica>>,
+    Path(_canister): Path<String>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    replica.peers.lock().unwrap().insert(peer);
+    let content = envelope(&body);
+    let request_id: RequestId = content.to_request_id();
+    let EnvelopeContent::Call {
+        method_name, arg, ..
+    } = content
+    else {
+        return StatusCode::BAD_REQUEST.into_response();
+    };
+    replica.calls.lock().unwrap().push(Call {
+        method: method_name.clone(),
+        arg: arg.clone(),
+        headers,
+    });
+
+    let in_flight = replica.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+    replica.max_in_flight.fetch_max(in_flight, Ordering::SeqCst);
+    let reply = match replica.scripted(&method_name) {
+        Some(reply) => reply,
+        None => replica.counter(&method_name, &arg).await,
+    };
+    replica.in_flight.fetch_sub(1, Ordering::SeqCst);
+
+    let certificate = match &reply {
+        Reply::Value(_) => replica.status_certificate(&request_id[..], "replied", Some(&reply)),
+        Reply::Reject(..) => replica.status_certificate(&request_id[..], "rejected", Some(&reply)),
+        Reply::Processing => return StatusCode::ACCEPTED.into_response(),
+        Reply::Http(status) => {
+            return (StatusCode::from_u16(*status).unwrap(), "scripted failure").into_response()
+        }
+    };
+    let response = TransportCallResponse::Replied { certificate };
+    (
+        [("content-type", "application/cbor")],
+        serde_cbor::to_vec(&response).unwrap(),
+    )
+        .into_response()
+}
+
+async fn query(
+    State(replica): State<Arc<Replica>>,
+    Path(_canister): Path<String>,
+    body: Bytes,
+) -> Response {
+    let EnvelopeContent::Query {
+        method_name, arg, ..
+    } = envelope(&body)
+    else {
+        return StatusCode::BAD_REQUEST.into_response();
+    };
+    replica.queries.lock().unwrap().push(method_name.clone());
+    let reply = match replica.scripted(&method_name) {
+        Some(reply) => reply,
+        None => replica.counter(&method_name, &arg).await,
+    };
+    match reply {
+        Reply::Value(arg) => cbor(Value::Map(BTreeMap::from([
+            (text("status"), text("replied")),
+            (
+                text("reply"),
+                Value::Map(BTreeMap::from([(text("arg"), Value::Bytes(arg))])),
+            ),
+        ]))),
+        Reply::Reject(code, message) => cbor(Value::Map(BTreeMap::from([
+            (text("status"), text("rejected")),
+            (text("reject_code"), Value::Integer(code as i128)),
+            (text("reject_message"), Value::Text(message)),
+        ]))),
+        Reply::Processing => StatusCode::ACCEPTED.into_response(),
+        Reply::Http(status) => {
+            (StatusCode::from_u16(status).unwrap(), "scripted failure").into_response()
+        }
+    }
+}
+
+/// Every update the replica is asked about is still processing
+async fn read_state(
+    State(replica): State<Arc<Replica>>,
+    Path(_canister): Path<String>,
+    body: Bytes,
+) -> Response {
+    let EnvelopeContent::ReadState { paths, .. } = envelope(&body) else {
+        return StatusCode::BAD_REQUEST.into_response();
+    };
+    let path: Vec<&Label<Vec<u8>>> = paths
+        .first()
+        .map(|path| path.iter().collect())
+        .unwrap_or_default();
+    if let Some(first) = path.first() {
+        replica
+            .read_states
+            .lock()
+            .unwrap()
+            .push(String::from_utf8_lossy(first.as_bytes()).into_owned());
+    }
+    let [status, request_id, ..] = path.as_slice() else {
+        return StatusCode::BAD_REQUEST.into_response();
+    };
+    if status.as_bytes() != b"request_status" {
+        return StatusCode::BAD_REQUEST.into_response();
+    }
+    let certificate = replica.status_certificate(request_id.as_bytes(), "processing", None);
+    cbor(Value::Map(BTreeMap::from([(
+        text("certificate"),
+        Value::Bytes(certificate),
+    )])))
+}